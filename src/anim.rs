@@ -0,0 +1,136 @@
+//! Purely cosmetic animations.
+//!
+//! The game state always changes immediately; animations only affect how the change is drawn
+//! over the next few frames, so they never hold up the game logic (or the AI).
+
+use graphics::Context;
+use opengl_graphics::GlGraphics;
+
+use super::{Game, CELL_SIZE, cell_pos};
+
+#[derive(Copy, Clone, Debug)]
+pub enum Effect {
+    /// A unit's head moving from one tile to the next.
+    Slide { from: (i16, i16), to: (i16, i16) },
+    /// A cell lost to damage sinking into the ground.
+    Sink { pos: (i16, i16), colour: [f32; 3] },
+    /// A floor tile appearing where there was nothing.
+    Rise { pos: (i16, i16) },
+    /// The flash of an attack hitting a tile.
+    Flash { pos: (i16, i16) },
+}
+
+impl Effect {
+    /// How many frames the effect lasts for.
+    fn length(&self) -> u64 {
+        match *self {
+            Effect::Slide { .. } => 6,
+            Effect::Sink { .. } => 20,
+            Effect::Rise { .. } => 15,
+            Effect::Flash { .. } => 10,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Animation {
+    effect: Effect,
+    start: u64,
+}
+
+impl Animation {
+    /// How far through the animation we are at `frame`, from 0 to 1.
+    fn progress(&self, frame: u64) -> f64 {
+        let t = (frame - self.start) as f64 / self.effect.length() as f64;
+        if t > 1.0 { 1.0 } else { t }
+    }
+}
+
+pub struct Animations {
+    list: Vec<Animation>,
+}
+
+impl Animations {
+    pub fn new() -> Animations {
+        Animations {
+            list: vec![],
+        }
+    }
+
+    pub fn add(&mut self, frame: u64, effect: Effect) {
+        self.list.push(Animation {
+            effect: effect,
+            start: frame,
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+
+    /// Forgets the animations that have finished by `frame`.
+    pub fn update(&mut self, frame: u64) {
+        self.list.retain(|a| frame < a.start + a.effect.length());
+    }
+
+    /// If a unit's head has just moved to `pos`, returns how far from `pos` it should be drawn,
+    /// in pixels.
+    pub fn slide_offset(&self, frame: u64, pos: (i16, i16)) -> (f64, f64) {
+        for anim in self.list.iter().rev() {
+            if let Effect::Slide { from, to } = anim.effect {
+                if to == pos {
+                    let left = 1.0 - anim.progress(frame);
+                    return ((cell_pos(from.0) - cell_pos(to.0)) * left,
+                            (cell_pos(from.1) - cell_pos(to.1)) * left)
+                }
+            }
+        }
+        (0.0, 0.0)
+    }
+
+    /// Returns whether the floor tile at `pos` is still appearing, and so is drawn here rather
+    /// than by the grid.
+    pub fn is_rising(&self, pos: (i16, i16)) -> bool {
+        self.list.iter().any(|a| match a.effect {
+            Effect::Rise { pos: p } => p == pos,
+            _ => false,
+        })
+    }
+
+    pub fn draw(&self, game: &Game, c: &Context, gl: &mut GlGraphics) {
+        use graphics::*;
+
+        for anim in &self.list {
+            let t = anim.progress(game.frame);
+            match anim.effect {
+                Effect::Slide { .. } => {},
+                Effect::Sink { pos: (x, y), colour } => {
+                    if !game.can_see(game.viewer, x, y) { continue }
+                    let size = CELL_SIZE * (1.0 - t);
+                    let gap = (CELL_SIZE - size) / 2.0;
+                    rectangle([colour[0], colour[1], colour[2], (1.0 - t) as f32],
+                              [cell_pos(x) + gap, cell_pos(y) + gap, size, size],
+                              c.transform,
+                              gl);
+                },
+                Effect::Rise { pos: (x, y) } => {
+                    if !game.can_see(game.viewer, x, y) { continue }
+                    let size = CELL_SIZE * t;
+                    let gap = (CELL_SIZE - size) / 2.0;
+                    rectangle([1.0, 1.0, 1.0, 0.3],
+                              [cell_pos(x) + gap, cell_pos(y) + gap, size, size],
+                              c.transform,
+                              gl);
+                },
+                Effect::Flash { pos: (x, y) } => {
+                    if !game.can_see(game.viewer, x, y) { continue }
+                    rectangle([1.0, 1.0, 1.0, (0.8 * (1.0 - t)) as f32],
+                              [cell_pos(x) - 2.0, cell_pos(y) - 2.0,
+                               CELL_SIZE + 4.0, CELL_SIZE + 4.0],
+                              c.transform,
+                              gl);
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Where the grid is drawn in the window.
+//!
+//! The grid, its units and their animations are drawn in the grid's own coordinates, where each
+//! cell is `CELL_SIZE` across (see `cell_pos`). The camera scales the grid to fit the part of the
+//! window beside the side panel and centres it there, and the same transform is used to work out
+//! which tile the mouse is over.
+//!
+//! Grids too big to fit at a readable size (or zoomed in on) are scrolled instead: the camera
+//! keeps a focus point in the middle of the view, which can be panned around or made to follow
+//! the action.
+
+use graphics::{Context, Transformed};
+
+use super::{Grid, CELL_SIZE, CELL_PADDING, cell_pos};
+use hud::PANEL_WIDTH;
+
+/// The space left around the grid when it is fitted to the window, in pixels.
+const MARGIN: f64 = 20.0;
+const MIN_ZOOM: f64 = 0.5;
+const MAX_ZOOM: f64 = 3.0;
+/// The smallest scale a grid is shrunk to to fit it in the window. Bigger grids are scrolled.
+const MIN_FIT: f64 = 0.75;
+/// How much of the way to its target the focus moves each frame when following something.
+const FOLLOW_SPEED: f64 = 0.2;
+
+pub struct Camera {
+    /// How many pixels across each unit of the grid's coordinates is.
+    pub scale: f64,
+    /// Where the grid's origin is in the window.
+    pub offset: (f64, f64),
+    /// How far the player has zoomed in from the scale that fits the grid to the window.
+    pub zoom: f64,
+    /// The point in the grid's coordinates shown in the middle of the view. Along each axis that
+    /// the grid fits in the view, it is kept in the middle of the grid.
+    pub focus: (f64, f64),
+    /// The point the focus is moving towards, if it is following something.
+    target: Option<(f64, f64)>,
+}
+
+impl Camera {
+    pub fn new() -> Camera {
+        Camera {
+            scale: 1.0,
+            offset: (0.0, 0.0),
+            zoom: 1.0,
+            focus: (0.0, 0.0),
+            target: None,
+        }
+    }
+
+    /// Scales `grid` to a window of the given size and works out where it goes.
+    pub fn fit(&mut self, grid: &Grid, size: [f64; 2]) {
+        // The grid's size, not counting the padding after the last row and column
+        let width = cell_pos(grid.width as i16) - CELL_PADDING;
+        let height = cell_pos(grid.height() as i16) - CELL_PADDING;
+        let area = [(size[0] - PANEL_WIDTH).max(CELL_SIZE), size[1]];
+        let fitted = ((area[0] - 2.0*MARGIN) / width).min((area[1] - 2.0*MARGIN) / height);
+        self.scale = fitted.max(MIN_FIT) * self.zoom;
+
+        let margin = MARGIN / self.scale;
+        let clamp = |focus: f64, len: f64, view: f64| {
+            if len + 2.0*margin <= view {
+                len / 2.0
+            } else {
+                focus.max(view / 2.0 - margin).min(len - view / 2.0 + margin)
+            }
+        };
+        self.focus = (clamp(self.focus.0, width, area[0] / self.scale),
+                      clamp(self.focus.1, height, area[1] / self.scale));
+        self.offset = (area[0] / 2.0 - self.focus.0 * self.scale,
+                       area[1] / 2.0 - self.focus.1 * self.scale);
+    }
+
+    pub fn zoom_by(&mut self, factor: f64) {
+        self.zoom = (self.zoom * factor).max(MIN_ZOOM).min(MAX_ZOOM);
+    }
+
+    /// Moves the view by the given distance in the grid's coordinates, and stops following
+    /// anything.
+    pub fn pan(&mut self, dx: f64, dy: f64) {
+        self.focus.0 += dx;
+        self.focus.1 += dy;
+        self.target = None;
+    }
+
+    /// Moves the view by the given distance in pixels.
+    pub fn pan_pixels(&mut self, dx: f64, dy: f64) {
+        let scale = self.scale;
+        self.pan(dx / scale, dy / scale);
+    }
+
+    /// Starts moving the view to the given tile.
+    pub fn follow(&mut self, (x, y): (i16, i16)) {
+        self.target = Some((cell_pos(x) + CELL_SIZE / 2.0, cell_pos(y) + CELL_SIZE / 2.0));
+    }
+
+    /// Moves the view towards whatever it is following.
+    pub fn update(&mut self) {
+        if let Some((x, y)) = self.target {
+            self.focus.0 += (x - self.focus.0) * FOLLOW_SPEED;
+            self.focus.1 += (y - self.focus.1) * FOLLOW_SPEED;
+            if (x - self.focus.0).abs() < 0.5 && (y - self.focus.1).abs() < 0.5 {
+                self.target = None;
+            }
+        }
+    }
+
+    /// Changes `c` to draw in the grid's coordinates.
+    pub fn transform(&self, c: &Context) -> Context {
+        c.trans(self.offset.0, self.offset.1).zoom(self.scale)
+    }
+
+    /// The tile at the given point in the window. Points in the padding between tiles belong to
+    /// the tile above or to the left.
+    pub fn tile_at(&self, (x, y): (f64, f64)) -> (i16, i16) {
+        let x = (x - self.offset.0) / self.scale;
+        let y = (y - self.offset.1) / self.scale;
+        ((x / (CELL_SIZE + CELL_PADDING)).floor() as i16,
+         (y / (CELL_SIZE + CELL_PADDING)).floor() as i16)
+    }
+}
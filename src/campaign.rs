@@ -0,0 +1,188 @@
+//! The campaign: levels joined up into a map, with story in between.
+//!
+//! The campaign is read from `campaign/campaign.txt`. Each line of the file is a directive:
+//!
+//! - `node <id> <x> <y> <level> <title...>` adds a level to the map, at column `x` and row `y`.
+//! - `link <id> <id>` joins two levels. Beating a level unlocks the ones it is joined to.
+//! - `start <id>` makes a level unlocked from the beginning.
+//! - `before <id> <file>` and `after <id> <file>` give the story shown before playing a level and
+//!   after winning it.
+//! - `reward <id> <credits>` gives the player credits the first time they win a level, on top of
+//!   any they pick up while playing it.
+//!
+//! Level and story files are found relative to the `campaign` directory. Blank lines and lines
+//! starting with `;` are ignored.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use profile::Profile;
+
+pub struct Node {
+    /// The name the node goes by in the campaign file and the player's profile.
+    pub id: String,
+    pub title: String,
+    pub level: PathBuf,
+    /// Where the node is on the map, in columns and rows.
+    pub pos: (i16, i16),
+    /// The nodes this one is joined to.
+    pub links: Vec<usize>,
+    pub start: bool,
+    /// The credits for winning the level for the first time.
+    pub reward: u32,
+    /// The lines of story shown before the level is played.
+    pub before: Vec<String>,
+    /// The lines of story shown after the level is won.
+    pub after: Vec<String>,
+}
+
+pub struct Campaign {
+    pub nodes: Vec<Node>,
+}
+
+fn read_to_string<P: AsRef<Path>>(path: P) -> String {
+    let mut f = File::open(path).unwrap();
+    let mut s = String::new();
+    f.read_to_string(&mut s).unwrap();
+    s
+}
+
+impl Campaign {
+    pub fn load() -> Campaign {
+        Campaign::from_string(&read_to_string("campaign/campaign.txt"), Path::new("campaign"))
+    }
+
+    /// Parses a campaign file, with the files it mentions found relative to `dir`.
+    pub fn from_string(s: &str, dir: &Path) -> Campaign {
+        let mut campaign = Campaign {
+            nodes: vec![],
+        };
+        for line in s.lines().map(|l| l.trim()) {
+            if line.is_empty() || line.starts_with(";") { continue }
+            let words: Vec<_> = line.split_whitespace().collect();
+            match words[0] {
+                "node" => {
+                    assert!(words.len() >= 6, "expected `node <id> <x> <y> <level> <title...>`: {}",
+                            line);
+                    assert!(campaign.find(words[1]).is_none(), "node {} declared twice", words[1]);
+                    campaign.nodes.push(Node {
+                        id: words[1].into(),
+                        title: words[5..].join(" "),
+                        level: dir.join(words[4]),
+                        pos: (words[2].parse().unwrap(), words[3].parse().unwrap()),
+                        links: vec![],
+                        start: false,
+                        reward: 0,
+                        before: vec![],
+                        after: vec![],
+                    });
+                },
+                "link" => {
+                    assert!(words.len() == 3, "expected `link <id> <id>`: {}", line);
+                    let a = campaign.expect(words[1]);
+                    let b = campaign.expect(words[2]);
+                    campaign.nodes[a].links.push(b);
+                    campaign.nodes[b].links.push(a);
+                },
+                "start" => {
+                    assert!(words.len() == 2, "expected `start <id>`: {}", line);
+                    let node = campaign.expect(words[1]);
+                    campaign.nodes[node].start = true;
+                },
+                "reward" => {
+                    assert!(words.len() == 3, "expected `reward <id> <credits>`: {}", line);
+                    let node = campaign.expect(words[1]);
+                    campaign.nodes[node].reward = words[2].parse().unwrap();
+                },
+                "before" | "after" => {
+                    assert!(words.len() == 3, "expected `{} <id> <file>`: {}", words[0], line);
+                    let node = campaign.expect(words[1]);
+                    let text = read_to_string(dir.join(words[2]));
+                    let lines = text.lines().map(|l| l.trim().into()).collect();
+                    if words[0] == "before" {
+                        campaign.nodes[node].before = lines;
+                    } else {
+                        campaign.nodes[node].after = lines;
+                    }
+                },
+                d => panic!("unknown campaign directive: {}", d),
+            }
+        }
+        assert!(campaign.nodes.iter().any(|node| node.start), "the campaign has no start node");
+        campaign
+    }
+
+    pub fn find(&self, id: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.id == id)
+    }
+
+    fn expect(&self, id: &str) -> usize {
+        self.find(id).unwrap_or_else(|| panic!("unknown campaign node {}", id))
+    }
+
+    /// Whether the given node can be played: it is a start node, or one of the nodes it is joined
+    /// to has been beaten.
+    pub fn is_unlocked(&self, node: usize, profile: &Profile) -> bool {
+        let node = &self.nodes[node];
+        node.start || node.links.iter().any(|&n| profile.has_beaten(&self.nodes[n].id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use profile::Profile;
+    use super::Campaign;
+
+    const CAMPAIGN: &'static str = "\
+; comment
+
+node first 0 0 first.sunrise The First Level
+node second 1 0 second.sunrise Second
+node third 1 1 third.sunrise Third
+link first second
+start first
+reward second 50
+";
+
+    #[test]
+    fn campaigns_are_read() {
+        let campaign = Campaign::from_string(CAMPAIGN, Path::new("dir"));
+        assert_eq!(campaign.nodes.len(), 3);
+        let first = &campaign.nodes[0];
+        assert_eq!(first.id, "first");
+        assert_eq!(first.title, "The First Level");
+        assert_eq!(first.level, Path::new("dir").join("first.sunrise"));
+        assert_eq!(first.links, [1]);
+        assert_eq!(campaign.nodes[1].pos, (1, 0));
+        assert_eq!(campaign.nodes[1].links, [0]);
+        assert_eq!(campaign.nodes[1].reward, 50);
+
+        let mut profile = Profile::new();
+        assert!(campaign.is_unlocked(0, &profile));
+        assert!(!campaign.is_unlocked(1, &profile));
+        profile.beat("first");
+        assert!(campaign.is_unlocked(1, &profile));
+        assert!(!campaign.is_unlocked(2, &profile));
+    }
+
+    #[test]
+    #[should_panic(expected = "node first declared twice")]
+    fn nodes_can_only_be_declared_once() {
+        Campaign::from_string("node first 0 0 a.sunrise A\nnode first 1 0 b.sunrise B\n",
+                              Path::new("dir"));
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown campaign node fourth")]
+    fn links_need_declared_nodes() {
+        Campaign::from_string(&format!("{}link third fourth\n", CAMPAIGN), Path::new("dir"));
+    }
+
+    #[test]
+    #[should_panic(expected = "the campaign has no start node")]
+    fn campaigns_need_a_start() {
+        Campaign::from_string("node first 0 0 a.sunrise A\n", Path::new("dir"));
+    }
+}
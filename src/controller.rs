@@ -1,17 +1,53 @@
-use super::{Game, CELL_SIZE, CELL_PADDING, CELL_OFFSET_X, CELL_OFFSET_Y};
-use piston::input::{Button, Key, MouseButton};
+use super::Game;
+use unit::UnitId;
+use game::Action;
+use grid::Cell;
+use hud;
+use keys::Control;
+use piston::input::{Button, MouseButton};
+
+/// How many tiles further from its target the AI treats a damaging tile as being, so that it only
+/// heads for one when that gets it a lot closer. Paths also go around them when they can.
+const DAMAGE_COST: i16 = 2;
 
 pub trait Controller {
     fn handle_press(&mut self, game: &mut Game, button: Button);
+    /// Handles a control given by something other than the keyboard, like a gamepad.
+    fn handle_control(&mut self, game: &mut Game, control: Control);
     fn handle_mouse(&mut self, game: &mut Game);
     fn handle_frame(&mut self, game: &mut Game);
     fn is_local_controlled(&self) -> bool;
 }
 
+/// The kinds of controller a team can be given in a level file or on the command line.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ControllerKind {
+    Local,
+    Ai,
+}
+
+impl ControllerKind {
+    pub fn from_name(name: &str) -> Option<ControllerKind> {
+        match name {
+            "local" => Some(ControllerKind::Local),
+            "ai" => Some(ControllerKind::Ai),
+            _ => None,
+        }
+    }
+
+    pub fn create(&self) -> Box<Controller> {
+        match *self {
+            ControllerKind::Local => Box::new(LocalController),
+            ControllerKind::Ai => Box::new(AiController::new()),
+        }
+    }
+}
+
 pub struct DummyController;
 
 impl Controller for DummyController {
     fn handle_press(&mut self, _: &mut Game, _: Button) { panic!() }
+    fn handle_control(&mut self, _: &mut Game, _: Control) { panic!() }
     fn handle_mouse(&mut self, _: &mut Game) { panic!() }
     fn handle_frame(&mut self, _: &mut Game) { panic!() }
     fn is_local_controlled(&self) -> bool { panic!() }
@@ -19,144 +55,99 @@ impl Controller for DummyController {
 
 pub struct LocalController;
 
-fn coords_to_tile((mut x, mut y): (f64, f64)) -> (i16, i16) {
-    x -= CELL_OFFSET_X;
-    y -= CELL_OFFSET_Y;
-    x /= CELL_SIZE + CELL_PADDING;
-    y /= CELL_SIZE + CELL_PADDING;
-    (x as i16, y as i16)
-}
-
 impl Controller for LocalController {
     fn handle_press(&mut self, game: &mut Game, button: Button) {
         match button {
-            Button::Keyboard(k) => match k {
-                Key::Left | Key::Right | Key::Up | Key::Down =>
-                    game.for_each_unit(|unit, game, _| {
-                        if unit.is_player(game) && unit.selected {
-                            let (dx, dy);
-                            match k {
-                                Key::Up => {
-                                    dx = 0;
-                                    dy = -1;
-                                },
-                                Key::Down => {
-                                    dx = 0;
-                                    dy = 1;
-                                },
-                                Key::Left => {
-                                    dx = -1;
-                                    dy = 0;
-                                },
-                                Key::Right => {
-                                    dx = 1;
-                                    dy = 0;
-                                },
-                                _ => unreachable!(),
-                            }
-                            unit.relocate(game, dx, dy);
-                        }
-                    }),
-                Key::Tab => {
-                    game.select_next();
-                },
-                Key::Q => { // XXX debugging
-                    game.for_each_unit(|unit, _, _| {
-                        unit.moves = unit.move_limit;
-                        unit.has_attacked = false;
-                    });
-                    let idx = game.selected_idx.unwrap_or(0);
-                    game.select(idx);
-                },
-                Key::D1 => {
-                    let idx = game.selected_idx.unwrap_or(0);
-                    game.attack(idx, 0);
-                },
-                Key::D2 => {
-                    let idx = game.selected_idx.unwrap_or(0);
-                    game.attack(idx, 1);
-                },
-                Key::Return => {
-                    if let Some(idx) = game.selected_idx {
-                        game.fire(idx);
-                    }
-                },
-                Key::Space => {
-                    game.next_team();
-                },
-                Key::U => {
-                    game.undo();
-                },
-                _ => {},
+            Button::Keyboard(k) => match game.bindings.control(k) {
+                Some(control) => self.handle_control(game, control),
+                None => {},
             },
             Button::Mouse(MouseButton::Left) => {
-                let (x, y) = coords_to_tile(game.mouse);
+                // Clicks on the side panel are for its button, not the tiles hidden under it
+                if game.mouse.0 >= game.view_size[0] - hud::PANEL_WIDTH {
+                    if hud::is_inside(hud::end_turn_button(game.view_size), game.mouse) {
+                        game.perform(Action::EndTurn);
+                    }
+                    return
+                }
+                let (x, y) = game.camera.tile_at(game.mouse);
 
                 // Selecting
                 if game.grid.attack_loc.is_none() {
-                    let mut idx = None;
-                    game.for_each_unit(|unit, game, i| {
+                    let mut found = None;
+                    game.for_each_unit(|unit, game, id| {
                         if unit.parts[0] == (x, y) && unit.team == game.current_team {
-                            idx = Some(i);
+                            found = Some(id);
                         }
                     });
-                    if let Some(idx) = idx {
-                        game.select(idx);
+                    if let Some(id) = found {
+                        game.perform(Action::Select(id));
                         return
                     }
                 }
 
-                let mut success = false;
                 // Moving or attacking
-                if let Some(idx) = game.selected_idx {
-                    let mut attack = false;
-                    game.for_unit(idx, |unit, game| {
-                        if unit.attack.is_some() {
-                            let width = game.grid.width as i16;
-                            for (idx, &v) in game.grid.attack_hi.iter().enumerate() {
-                                let idx = idx as i16;
-                                if v > 0 && (x, y) == (idx % width, idx / width) {
-                                    attack = true;
-                                    break
-                                }
-                            }
-                        } else {
-                            let (hx, hy) = unit.parts[0];
-                            if [(hx + 1, hy), (hx - 1, hy),
-                                (hx, hy + 1), (hx, hy - 1)].contains(&(x, y)) {
-                                unit.relocate(game, x - hx, y - hy);
-                                success = true;
-                            }
+                let selected = game.selected_unit()
+                                   .map(|unit| (unit.attack.is_some(), unit.parts[0]));
+                if let Some((attacking, (hx, hy))) = selected {
+                    if attacking {
+                        if game.grid.is_in_bounds(x, y)
+                        && game.grid.attack_hi[(x + game.grid.width as i16*y) as usize] > 0 {
+                            game.perform(Action::Target(x, y));
+                            game.perform(Action::Fire);
+                        }
+                    } else {
+                        if [(hx + 1, hy), (hx - 1, hy),
+                            (hx, hy + 1), (hx, hy - 1)].contains(&(x, y)) {
+                            game.perform(Action::Move(x - hx, y - hy));
                         }
-                    });
-                    if attack {
-                        game.grid.attack_loc = Some((x, y));
-                        game.fire(idx);
-                        success = true;
                     }
                 }
-
-                if success { return }
             },
             _ => {},
         }
     }
 
-    fn handle_mouse(&mut self, game: &mut Game) {
-        let (x, y) = coords_to_tile(game.mouse);
-        if let Some(idx) = game.selected_idx {
-            game.for_unit(idx, |unit, game| {
-                if unit.attack.is_some() {
-                    let width = game.grid.width as i16;
-                    for (idx, &v) in game.grid.attack_hi.iter().enumerate() {
-                        let idx = idx as i16;
-                        if v > 0 && (x, y) == (idx % width, idx / width) {
-                            game.grid.attack_loc = Some((x, y));
-                            break
-                        }
-                    }
+    fn handle_control(&mut self, game: &mut Game, control: Control) {
+        match control {
+            Control::Up => game.perform(Action::Move(0, -1)),
+            Control::Down => game.perform(Action::Move(0, 1)),
+            Control::Left => game.perform(Action::Move(-1, 0)),
+            Control::Right => game.perform(Action::Move(1, 0)),
+            Control::Next => {
+                game.perform(Action::SelectNext);
+            },
+            Control::Attack(attack) => {
+                let exists = game.selected_unit().map_or(false, |unit| {
+                    (attack as usize) < unit.attacks.len()
+                });
+                if exists {
+                    game.perform(Action::Attack(attack));
                 }
-            });
+            },
+            Control::Fire => {
+                game.perform(Action::Fire);
+            },
+            Control::EndTurn => {
+                game.perform(Action::EndTurn);
+            },
+            Control::Undo => {
+                game.perform(Action::Undo);
+            },
+            // The game pans the view itself, whoever's turn it is
+            Control::Pan(..) => {},
+        }
+    }
+
+    fn handle_mouse(&mut self, game: &mut Game) {
+        if game.mouse.0 >= game.view_size[0] - hud::PANEL_WIDTH { return }
+        let (x, y) = game.camera.tile_at(game.mouse);
+        if let Some(attacking) = game.selected_unit().map(|unit| unit.attack.is_some()) {
+            if attacking && game.grid.attack_loc != Some((x, y))
+            && game.grid.is_in_bounds(x, y)
+            && game.grid.attack_hi[(x + game.grid.width as i16*y) as usize] > 0 {
+                game.perform(Action::Target(x, y));
+            }
         }
     }
 
@@ -167,8 +158,10 @@ impl Controller for LocalController {
 
 pub struct AiController {
     delay: u16,
-    /// Vector of the postitions of enemy cells & the index of the unit they are part of
-    enemy_positions: Vec<(i16, i16, usize)>,
+    /// Vector of the postitions of visible enemy cells & the id of the unit they are part of
+    enemy_positions: Vec<(i16, i16, UnitId)>,
+    /// `Game::spawned` when `enemy_positions` was last updated, so that new units are noticed.
+    spawned: u64,
     /// Path to the desired location in reverse order.
     path: Option<Vec<(i16, i16)>>,
 }
@@ -178,16 +171,21 @@ impl AiController {
         AiController {
             delay: 9,
             enemy_positions: vec![],
+            spawned: 0,
             path: None,
         }
     }
 
     fn update_enemy_positions(&mut self, game: &mut Game) {
         self.enemy_positions.clear();
-        game.for_each_unit(|unit, game, idx| {
+        self.spawned = game.spawned;
+        let team = game.current_team;
+        game.for_each_unit(|unit, game, id| {
             if unit.is_player(game) { return }
             for &(x, y) in &unit.parts {
-                self.enemy_positions.push((x, y, idx));
+                if game.can_see(team, x, y) {
+                    self.enemy_positions.push((x, y, id));
+                }
             }
         });
     }
@@ -196,10 +194,12 @@ impl AiController {
 impl Controller for AiController {
     fn handle_press(&mut self, _: &mut Game, _: Button) {}
 
+    fn handle_control(&mut self, _: &mut Game, _: Control) {}
+
     fn handle_mouse(&mut self, _: &mut Game) {}
 
     fn handle_frame(&mut self, game: &mut Game) {
-        if self.enemy_positions.is_empty() {
+        if self.enemy_positions.is_empty() || self.spawned != game.spawned {
             self.update_enemy_positions(game);
         }
 
@@ -208,14 +208,14 @@ impl Controller for AiController {
             return
         }
         self.delay = 9;
-        if let Some(curr) = game.selected_idx {
+        if let Some(curr) = game.selected_unit().cloned() {
             if game.grid.attack_loc.is_some() {
-                game.fire(curr);
+                game.perform(Action::Fire);
                 self.update_enemy_positions(game);
                 return
             }
 
-            if game.units[curr].has_attacked {
+            if curr.has_attacked {
                 let mut all = true;
                 game.for_each_unit(|unit, game, _| {
                     if unit.is_player(game) && !unit.has_attacked {
@@ -224,39 +224,39 @@ impl Controller for AiController {
                 });
                 if all {
                     self.enemy_positions = vec![];
-                    game.next_team();
+                    game.perform(Action::EndTurn);
                 } else {
-                    game.select_next();
+                    game.perform(Action::SelectNext);
+                    self.update_enemy_positions(game);
                 }
                 return
             }
 
-            let (ux, uy) = game.units[curr].parts[0];
+            let (ux, uy) = curr.parts[0];
 
-            let range = game.units[curr].attacks.iter().map(|x| x.range()).max().unwrap() as i16;
+            let range = curr.attacks.iter().map(|x| x.range()).max().unwrap() as i16;
 
             let mut empty = false;
             if let Some(ref mut path) = self.path {
                 if let Some((dx, dy)) = path.pop() {
-                    game.for_unit(curr, |unit, game| {
-                        unit.relocate(game, dx, dy);
-                    });
+                    game.perform(Action::Move(dx, dy));
                 } else {
                     empty = true; // work around #6393
-                    if let Some((_, x, y)) = self.enemy_positions.iter().filter_map(|&(x, y, idx)| {
+                    if let Some((_, x, y)) = self.enemy_positions.iter().filter_map(|&(x, y, id)| {
                         let dist = (ux - x).abs() + (uy - y).abs();
                         if dist > range { return None }
-                        let health = game.units[idx].parts.len();
+                        let health = game.unit(id)?.parts.len();
                         Some((health, x, y))
                     }).max() {
-                        game.attack(curr, 0); // TODO: allow multiple AI attacks?
-                        game.grid.attack_loc = Some((x, y));
+                        game.perform(Action::Attack(0)); // TODO: allow multiple AI attacks?
+                        game.perform(Action::Target(x, y));
                     } else {
-                        let unit = &mut game.units[curr];
-                        unit.has_attacked = true;
-                        unit.moves = 0;
+                        game.perform(Action::Wait);
                     }
                 }
+            } else if self.enemy_positions.is_empty() {
+                // Nothing in sight to go after
+                game.perform(Action::Wait);
             } else {
                 // First, find the target tile
                 let width = game.grid.width as i16;
@@ -267,20 +267,34 @@ impl Controller for AiController {
                     let min_dist = self.enemy_positions.iter().map(|&(x, y, _)| {
                         ((ox - x).abs() + (oy - y).abs()).saturating_sub(range)
                     }).min().unwrap();
-                    Some((min_dist, ox, oy))
+                    Some((min_dist + cost(game.grid[(ox, oy)]), ox, oy))
                 }).min().unwrap();
 
-                // Next, find the path to the target tile
+                // Next, find the path to the target tile by stepping back through the tiles that
+                // were highlighted one move earlier. Units can't move on from slowing tiles, so
+                // the path can't go through them, unless it's where the unit started out.
                 let mut path = Vec::new();
                 while (x, y) != (ux, uy) {
-                    for &(dx, dy) in [(0, 1), (0, -1), (1, 0), (-1, 0)].iter() {
-                        if game.grid.highlight[(x + dx + width*(y + dy)) as usize]
-                                == game.grid.highlight[(x + width*y) as usize] + 1 {
+                    let moves = game.grid.highlight[(x + width*y) as usize];
+                    let step = [(0, 1), (0, -1), (1, 0), (-1, 0)].iter().cloned()
+                        .filter(|&(dx, dy)| {
+                            let (px, py) = (x + dx, y + dy);
+                            game.grid.is_in_bounds(px, py)
+                                && game.grid.highlight[(px + width*py) as usize] == moves + 1
+                                && (game.grid[(px, py)] != Cell::Slow || (px, py) == (ux, uy))
+                        })
+                        .min_by_key(|&(dx, dy)| cost(game.grid[(x + dx, y + dy)]));
+                    match step {
+                        Some((dx, dy)) => {
                             x += dx;
                             y += dy;
                             path.push((-dx, -dy));
+                        },
+                        // Shouldn't happen, but waiting is better than getting stuck here
+                        None => {
+                            path.clear();
                             break
-                        }
+                        },
                     }
                 }
                 self.path = Some(path);
@@ -291,3 +305,8 @@ impl Controller for AiController {
 
     fn is_local_controlled(&self) -> bool { false }
 }
+
+/// How much going over a tile costs the AI, on top of the move it takes.
+fn cost(cell: Cell) -> i16 {
+    if cell == Cell::Damage { DAMAGE_COST } else { 0 }
+}
@@ -0,0 +1,311 @@
+//! Uploading units onto the grid before a battle.
+//!
+//! Levels with upload zones start with the first local team choosing which units from its roster
+//! to bring and where to put them. Units are picked from the list in the side panel, or with the
+//! up and down controls, and placed by clicking on an upload zone, or by moving between the zones
+//! with the left and right controls and firing. Doing the same to a unit that has already been
+//! placed takes it back. Ending the turn or pressing Start starts the battle, and Escape or B
+//! leaves the level. The controls are the player's key bindings (see `keys`) or the gamepad.
+
+use piston::input::{Button, Key, MouseButton};
+use graphics::{Context, Rectangle, rectangle, default_draw_state};
+use opengl_graphics::GlGraphics;
+
+use super::{Game, Unit, CELL_SIZE, cell_pos};
+use grid::Cell;
+use unit::UnitId;
+use hud::{self, PANEL_WIDTH};
+use text::{Text, Align};
+use keys::Control;
+use pad::{PadEvent, PadButton, Stick};
+
+const MARGIN: f64 = 10.0;
+const ENTRY_HEIGHT: f64 = 28.0;
+const ENTRY_GAP: f64 = 6.0;
+/// How far down the panel the roster starts.
+const ROSTER_TOP: f64 = 60.0;
+const ZOOM_STEP: f64 = 1.25;
+
+/// Something for whatever is running the deployment to do.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+    /// Start the battle with the units that have been placed, using `Deployment::finish`.
+    Start,
+    Exit,
+}
+
+/// The kinds of unit a team can bring along when it doesn't have a roster of its own, which new
+/// profiles start out with.
+pub const DEFAULT_ROSTER: [&'static str; 3] = ["hack", "hack", "lightning"];
+
+/// Turns the upload zones into plain floor, for games that start without placing any units.
+pub fn clear_zones(game: &mut Game) {
+    for zone in game.grid.find(Cell::Upload) {
+        game.grid[zone] = Cell::Floor;
+    }
+    game.update_visibility();
+}
+
+pub fn default_roster() -> Vec<Unit> {
+    DEFAULT_ROSTER.iter()
+        .map(|kind| Unit::from_kind(kind, (0, 0)).unwrap())
+        .collect()
+}
+
+/// Where the `i`th unit of the roster is listed in a window of the given size.
+fn entry_rect(i: usize, size: [f64; 2]) -> [f64; 4] {
+    [size[0] - PANEL_WIDTH + MARGIN, ROSTER_TOP + i as f64 * (ENTRY_HEIGHT + ENTRY_GAP),
+     PANEL_WIDTH - 2.0*MARGIN, ENTRY_HEIGHT]
+}
+
+pub struct Deployment {
+    pub game: Game,
+    /// The team whose units are being placed.
+    team: u16,
+    roster: Vec<Unit>,
+    /// For each unit in the roster, its id in the game if it has been placed.
+    placed: Vec<Option<UnitId>>,
+    /// The upload zones, row by row.
+    zones: Vec<(i16, i16)>,
+    /// The unit in the roster that is placed next.
+    selected: usize,
+    /// The zone chosen with the keyboard.
+    cursor: usize,
+}
+
+impl Deployment {
+    /// Starts placing units from `roster` for the first local team, or gives the game back, with
+    /// its upload zones cleared, if there is nothing to place or nowhere to place it.
+    pub fn new(mut game: Game, roster: Vec<Unit>) -> Result<Deployment, Game> {
+        let zones = game.grid.find(Cell::Upload);
+        let team = match game.teams.iter().position(|team| team.is_local()) {
+            Some(team) if !zones.is_empty() && !roster.is_empty() => team as u16,
+            _ => {
+                clear_zones(&mut game);
+                return Err(game)
+            },
+        };
+        let mut deployment = Deployment {
+            game: game,
+            team: team,
+            placed: vec![None; roster.len()],
+            roster: roster,
+            zones: zones,
+            selected: 0,
+            cursor: 0,
+        };
+        deployment.game.viewer = team;
+        deployment.update_visibility();
+        Ok(deployment)
+    }
+
+    /// Finishes placing units, leaving the game ready to start. Upload zones that weren't used
+    /// become plain floor.
+    pub fn finish(mut self) -> Game {
+        clear_zones(&mut self.game);
+        self.game
+    }
+
+    /// Whether the team has any units to fight with.
+    fn can_start(&self) -> bool {
+        self.game.units.values().any(|unit| unit.team == self.team)
+    }
+
+    /// The unit in the roster that has been placed at `pos`, if there is one.
+    fn placed_at(&self, pos: (i16, i16)) -> Option<usize> {
+        let game = &self.game;
+        self.placed.iter().position(|&id| {
+            id.and_then(|id| game.unit(id)).map_or(false, |unit| unit.parts[0] == pos)
+        })
+    }
+
+    /// Places the selected unit on the zone at `pos`, or takes back the unit already there.
+    fn toggle(&mut self, pos: (i16, i16)) {
+        if !self.zones.contains(&pos) { return }
+        if let Some(i) = self.placed_at(pos) {
+            let id = self.placed[i].take().unwrap();
+            self.game.units.remove(&id);
+            self.selected = i;
+        } else {
+            // A unit that has already been placed moves to the new zone
+            if let Some(id) = self.placed[self.selected].take() {
+                self.game.units.remove(&id);
+            }
+            let mut unit = self.roster[self.selected].clone();
+            unit.parts.clear();
+            unit.parts.push_back(pos);
+            unit.team = self.team;
+            self.placed[self.selected] = Some(self.game.add_unit(unit));
+
+            // Move on to the next unit that hasn't been placed yet
+            let len = self.roster.len();
+            if let Some(next) = (1..len).map(|i| (self.selected + i) % len)
+                                        .find(|&i| self.placed[i].is_none()) {
+                self.selected = next;
+            }
+        }
+        self.update_visibility();
+    }
+
+    /// Works out what the team can see from the units it has placed so far. The upload zones can
+    /// always be seen, so that there is somewhere to put the first unit.
+    fn update_visibility(&mut self) {
+        self.game.update_visibility();
+        let width = self.game.grid.width;
+        for &(x, y) in &self.zones {
+            self.game.visibility[self.team as usize][x as usize + width*y as usize] = true;
+        }
+    }
+
+    fn handle_control(&mut self, control: Control) -> Option<Request> {
+        let len = self.roster.len();
+        let zones = self.zones.len();
+        match control {
+            Control::Up => self.selected = (self.selected + len - 1) % len,
+            Control::Down | Control::Next => self.selected = (self.selected + 1) % len,
+            Control::Left => self.cursor = (self.cursor + zones - 1) % zones,
+            Control::Right => self.cursor = (self.cursor + 1) % zones,
+            Control::Fire => {
+                let zone = self.zones[self.cursor];
+                self.toggle(zone);
+            },
+            Control::EndTurn if self.can_start() => return Some(Request::Start),
+            control @ Control::Pan(..) => { self.game.pan_control(Some(control)); },
+            _ => {},
+        }
+        None
+    }
+
+    pub fn handle_press(&mut self, button: Button) -> Option<Request> {
+        let len = self.roster.len();
+        match button {
+            Button::Keyboard(Key::Escape) => return Some(Request::Exit),
+            Button::Keyboard(Key::Equals) => self.game.camera.zoom_by(ZOOM_STEP),
+            Button::Keyboard(Key::Minus) => self.game.camera.zoom_by(1.0 / ZOOM_STEP),
+            Button::Keyboard(k) => {
+                if let Some(control) = self.game.bindings.control(k) {
+                    return self.handle_control(control)
+                }
+            },
+            Button::Mouse(MouseButton::Left) => {
+                let mouse = self.game.mouse;
+                let size = self.game.view_size;
+                if mouse.0 >= size[0] - PANEL_WIDTH {
+                    if hud::is_inside(hud::end_turn_button(size), mouse) && self.can_start() {
+                        return Some(Request::Start)
+                    }
+                    let entry = (0..len).find(|&i| hud::is_inside(entry_rect(i, size), mouse));
+                    if let Some(i) = entry {
+                        self.selected = i;
+                    }
+                    return None
+                }
+                let tile = self.game.camera.tile_at(mouse);
+                if let Some(zone) = self.zones.iter().position(|&zone| zone == tile) {
+                    self.cursor = zone;
+                    self.toggle(tile);
+                }
+            },
+            Button::Mouse(MouseButton::Right) => self.game.dragging = true,
+            _ => {},
+        }
+        None
+    }
+
+    /// Like `handle_press`, but for gamepads. The right stick pans the view.
+    pub fn handle_pad(&mut self, event: PadEvent) -> Option<Request> {
+        match event {
+            PadEvent::Press(PadButton::Start) if self.can_start() => Some(Request::Start),
+            PadEvent::Press(PadButton::B) => Some(Request::Exit),
+            PadEvent::Stick(Stick::Right, _, _) => {
+                let control = self.game.pad.control(event);
+                self.game.pan_towards(control);
+                None
+            },
+            event => match self.game.pad.control(event) {
+                Some(control) => self.handle_control(control),
+                None => None,
+            },
+        }
+    }
+
+    pub fn handle_release(&mut self, button: Button) {
+        self.game.handle_release(button);
+    }
+
+    pub fn handle_mouse(&mut self, x: f64, y: f64) {
+        let game = &mut self.game;
+        if game.dragging {
+            game.camera.pan_pixels(game.mouse.0 - x, game.mouse.1 - y);
+        }
+        game.mouse = (x, y);
+    }
+
+    pub fn handle_scroll(&mut self, amount: f64) {
+        self.game.handle_scroll(amount);
+    }
+
+    pub fn draw(&mut self, c: &Context, gl: &mut GlGraphics) {
+        self.game.draw_world(c, gl);
+        let game = &self.game;
+        let size = game.view_size;
+        let world = game.camera.transform(c);
+
+        // The zone chosen with the keyboard, and the one under the mouse
+        let (x, y) = self.zones[self.cursor];
+        Rectangle::new_border([1.0, 1.0, 1.0, 0.9], 1.5)
+            .draw([cell_pos(x) - 2.0, cell_pos(y) - 2.0, CELL_SIZE + 4.0, CELL_SIZE + 4.0],
+                  default_draw_state(), world.transform, gl);
+        let hovered = game.camera.tile_at(game.mouse);
+        if hovered != (x, y) && self.zones.contains(&hovered)
+        && game.mouse.0 < size[0] - PANEL_WIDTH {
+            let (x, y) = hovered;
+            Rectangle::new_border([1.0, 1.0, 1.0, 0.5], 1.0)
+                .draw([cell_pos(x) - 2.0, cell_pos(y) - 2.0, CELL_SIZE + 4.0, CELL_SIZE + 4.0],
+                      default_draw_state(), world.transform, gl);
+        }
+
+        // The side panel, listing the roster
+        let left = size[0] - PANEL_WIDTH + MARGIN;
+        let width = PANEL_WIDTH - 2.0*MARGIN;
+        let white = Text::new([1.0, 1.0, 1.0, 1.0]);
+        let grey = Text::new([0.6, 0.6, 0.6, 1.0]);
+        rectangle([0.08, 0.08, 0.08, 1.0], [size[0] - PANEL_WIDTH, 0.0, PANEL_WIDTH, size[1]],
+                  c.transform, gl);
+        let team = &game.teams[self.team as usize];
+        let colour = team.colour;
+        rectangle([colour[0], colour[1], colour[2], 1.0], [left, MARGIN, width, 16.0],
+                  c.transform, gl);
+        Text::new([0.0, 0.0, 0.0, 1.0]).align(Align::Centre)
+            .draw(&team.name, &game.font, left + width / 2.0, MARGIN + 4.5, c, gl);
+        let used = self.placed.iter().filter(|idx| idx.is_some()).count();
+        grey.draw(&format!("Zones {}/{}", used, self.zones.len()), &game.font,
+                  left, MARGIN + 26.0, c, gl);
+
+        for (i, unit) in self.roster.iter().enumerate() {
+            let rect = entry_rect(i, size);
+            if i == self.selected {
+                rectangle([1.0, 1.0, 1.0, 0.2], rect, c.transform, gl);
+            }
+            let border = if self.placed[i].is_some() { 0.3 } else { 0.8 };
+            Rectangle::new_border([1.0, 1.0, 1.0, border], 0.5)
+                .draw(rect, default_draw_state(), c.transform, gl);
+            let name = if self.placed[i].is_some() { grey } else { white };
+            name.draw(unit.name, &game.font, rect[0] + 4.0, rect[1] + 4.0, c, gl);
+            grey.draw(&format!("Size {} Moves {}", unit.len_limit, unit.move_limit), &game.font,
+                      rect[0] + 4.0, rect[1] + 16.0, c, gl);
+        }
+
+        let button = hud::end_turn_button(size);
+        let alpha = if !self.can_start() {
+            0.2
+        } else if hud::is_inside(button, game.mouse) {
+            1.0
+        } else {
+            0.6
+        };
+        rectangle([colour[0], colour[1], colour[2], alpha], button, c.transform, gl);
+        Text::new([0.0, 0.0, 0.0, 1.0]).align(Align::Centre)
+            .draw("Start", &game.font, button[0] + button[2] / 2.0, button[1] + 11.5, c, gl);
+    }
+}
@@ -0,0 +1,430 @@
+//! The level editor.
+//!
+//! The left mouse button paints with the current tool: one of the kinds of cell, credits, or one
+//! of the kinds of unit for the current team. Everything else is done with the buttons in the side
+//! panel or their keys: F, E, W, U, D, L and X pick the floor, empty, wall, upload zone, data, slow
+//! and damaging cells, C the credits, 1 to 4 the units, T changes team, the arrow keys resize the
+//! grid, S saves, P play-tests the level and Escape leaves the editor.
+
+use std::path::PathBuf;
+use piston::input::{Button, Key, MouseButton};
+use graphics::{Context, Rectangle, clear, rectangle, default_draw_state};
+use opengl_graphics::GlGraphics;
+
+use super::{Unit, CELL_SIZE, cell_pos};
+use camera::Camera;
+use controller::ControllerKind;
+use grid::{self, Grid, Cell, CELLS};
+use hud::{self, PANEL_WIDTH};
+use item::Item;
+use level::{Level, TeamSpec, TEAM_COLOURS};
+use text::{Font, Text, Align};
+use unit::KINDS;
+
+const MARGIN: f64 = 10.0;
+const BUTTON_HEIGHT: f64 = 18.0;
+const BUTTON_GAP: f64 = 4.0;
+/// The most tiles a level can have across or down.
+const MAX_SIZE: usize = 100;
+/// How many credits are in each pile placed by the credits tool.
+const CREDITS: u32 = 10;
+const ZOOM_STEP: f64 = 1.25;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Tool {
+    Cell(Cell),
+    Credits,
+    /// Places units of the given entry of `unit::KINDS`.
+    Unit(usize),
+}
+
+/// The buttons in the editor's side panel.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum PanelButton {
+    Resize(i16, i16),
+    Tool(Tool),
+    Team,
+    Controller,
+    AddTeam,
+    Fog,
+    Save,
+    Play,
+    Back,
+}
+
+/// Something for whatever is running the editor to do.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+    /// Play the level as it is now, coming back to the editor afterwards.
+    PlayTest,
+    Exit,
+}
+
+pub struct Editor {
+    pub level: Level,
+    /// Where the level is saved to.
+    pub path: PathBuf,
+    tool: Tool,
+    /// The team units are placed on.
+    team: u16,
+    camera: Camera,
+    mouse: (f64, f64),
+    /// Whether the left mouse button is held down over the grid.
+    painting: bool,
+    /// Whether the view is being dragged around with the right mouse button.
+    dragging: bool,
+    /// Shown at the bottom of the panel, to say whether saving worked.
+    message: String,
+    /// The size of the window when it was last drawn.
+    view_size: [f64; 2],
+}
+
+/// Where each of the panel's buttons are in a window of the given size.
+fn buttons(size: [f64; 2]) -> Vec<(PanelButton, [f64; 4])> {
+    let left = size[0] - PANEL_WIDTH + MARGIN;
+    let width = PANEL_WIDTH - 2.0*MARGIN;
+    let mut buttons = vec![];
+
+    // Four small buttons in a row for resizing
+    let mut y = MARGIN + 40.0;
+    let small = (width - 3.0*BUTTON_GAP) / 4.0;
+    let resizes = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+    for (i, &(dw, dh)) in resizes.iter().enumerate() {
+        buttons.push((PanelButton::Resize(dw, dh),
+                      [left + i as f64 * (small + BUTTON_GAP), y, small, BUTTON_HEIGHT]));
+    }
+    y += BUTTON_HEIGHT + 2.0*BUTTON_GAP;
+
+    // The tools go two to a row, cells and then units
+    let half = (width - BUTTON_GAP) / 2.0;
+    let mut cells: Vec<_> = CELLS.iter().map(|&cell| Tool::Cell(cell)).collect();
+    cells.push(Tool::Credits);
+    let units: Vec<_> = (0..KINDS.len()).map(|k| Tool::Unit(k)).collect();
+    for tools in &[cells, units] {
+        for row in tools.chunks(2) {
+            for (i, &tool) in row.iter().enumerate() {
+                buttons.push((PanelButton::Tool(tool),
+                              [left + i as f64 * (half + BUTTON_GAP), y, half, BUTTON_HEIGHT]));
+            }
+            y += BUTTON_HEIGHT + BUTTON_GAP;
+        }
+        y += BUTTON_GAP;
+    }
+
+    for &button in &[PanelButton::Team, PanelButton::Controller, PanelButton::AddTeam,
+                     PanelButton::Fog] {
+        buttons.push((button, [left, y, width, BUTTON_HEIGHT]));
+        y += BUTTON_HEIGHT + BUTTON_GAP;
+    }
+
+    // Saving and leaving go at the bottom
+    let mut y = size[1] - MARGIN - BUTTON_HEIGHT;
+    for &button in &[PanelButton::Back, PanelButton::Play, PanelButton::Save] {
+        buttons.push((button, [left, y, width, BUTTON_HEIGHT]));
+        y -= BUTTON_HEIGHT + BUTTON_GAP;
+    }
+    buttons
+}
+
+impl Editor {
+    pub fn new(level: Level, path: PathBuf) -> Editor {
+        Editor {
+            level: level,
+            path: path,
+            tool: Tool::Cell(Cell::Floor),
+            team: 0,
+            camera: Camera::new(),
+            mouse: (0.0, 0.0),
+            painting: false,
+            dragging: false,
+            message: String::new(),
+            view_size: [0.0, 0.0],
+        }
+    }
+
+    pub fn open(path: PathBuf) -> Editor {
+        Editor::new(Level::from_path(&path), path)
+    }
+
+    fn unit_at(&self, pos: (i16, i16)) -> Option<usize> {
+        self.level.units.iter().find(|&(_, unit)| unit.parts[0] == pos).map(|(idx, _)| idx)
+    }
+
+    /// Uses the current tool on the tile at `pos`.
+    fn paint(&mut self, (x, y): (i16, i16)) {
+        if !self.level.grid.is_in_bounds(x, y) { return }
+        let existing = self.unit_at((x, y));
+        match self.tool {
+            Tool::Cell(cell) => {
+                // Units always start out on plain floor
+                if let Some(idx) = existing {
+                    if cell != Cell::Floor {
+                        self.level.units.remove(&idx);
+                    }
+                }
+                if !cell.is_walkable() {
+                    self.level.items.retain(|item| item.pos != (x, y));
+                }
+                self.level.grid[(x, y)] = cell;
+            },
+            Tool::Credits => {
+                let grid = &self.level.grid;
+                let items = &mut self.level.items;
+                if !grid.is_valid(x, y) || items.iter().any(|item| item.pos == (x, y)) { return }
+                items.push(Item {
+                    pos: (x, y),
+                    credits: CREDITS,
+                });
+            },
+            Tool::Unit(kind) => {
+                let mut unit = Unit::from_kind(KINDS[kind], (x, y)).unwrap();
+                unit.team = self.team;
+                if let Some(idx) = existing {
+                    let old = &self.level.units[idx];
+                    if old.name == unit.name && old.team == unit.team { return }
+                    self.level.units.remove(&idx);
+                }
+                let idx = self.level.units.keys().max().map_or(0, |idx| idx + 1);
+                self.level.units.insert(idx, unit);
+                self.level.grid[(x, y)] = Cell::Floor;
+            },
+        }
+        self.message.clear();
+    }
+
+    /// Adds or removes rows or columns at the right and bottom edges of the grid.
+    fn resize(&mut self, dw: i16, dh: i16) {
+        let old = &self.level.grid;
+        let width = (old.width as i16 + dw).max(1).min(MAX_SIZE as i16);
+        let height = (old.height() as i16 + dh).max(1).min(MAX_SIZE as i16);
+        let mut grid = Grid::filled(width as usize, height as usize, Cell::Floor);
+        for y in 0..height {
+            for x in 0..width {
+                if old.is_in_bounds(x, y) {
+                    grid[(x, y)] = old[(x, y)];
+                }
+            }
+        }
+        let lost: Vec<_> = self.level.units.iter()
+            .filter(|&(_, unit)| !grid.is_in_bounds(unit.parts[0].0, unit.parts[0].1))
+            .map(|(idx, _)| idx)
+            .collect();
+        for idx in lost {
+            self.level.units.remove(&idx);
+        }
+        self.level.items.retain(|item| grid.is_in_bounds(item.pos.0, item.pos.1));
+        self.level.grid = grid;
+        self.message.clear();
+    }
+
+    fn press(&mut self, button: PanelButton) -> Option<Request> {
+        match button {
+            PanelButton::Resize(dw, dh) => self.resize(dw, dh),
+            PanelButton::Tool(tool) => self.tool = tool,
+            PanelButton::Team => {
+                self.team = (self.team + 1) % self.level.teams.len() as u16;
+            },
+            PanelButton::Controller => {
+                let team = &mut self.level.teams[self.team as usize];
+                team.controller = match team.controller {
+                    ControllerKind::Local => ControllerKind::Ai,
+                    ControllerKind::Ai => ControllerKind::Local,
+                };
+            },
+            PanelButton::AddTeam => {
+                let n = self.level.teams.len();
+                if n < TEAM_COLOURS.len() {
+                    self.level.teams.push(TeamSpec {
+                        name: format!("Team{}", n + 1),
+                        controller: ControllerKind::Ai,
+                        colour: TEAM_COLOURS[n],
+                    });
+                    self.team = n as u16;
+                }
+            },
+            PanelButton::Fog => self.level.fog = !self.level.fog,
+            PanelButton::Save => {
+                self.message = match self.level.save(&self.path) {
+                    Ok(()) => "Saved".into(),
+                    Err(e) => format!("Couldn't save: {}", e),
+                };
+            },
+            PanelButton::Play => return Some(Request::PlayTest),
+            PanelButton::Back => return Some(Request::Exit),
+        }
+        None
+    }
+
+    pub fn handle_press(&mut self, button: Button) -> Option<Request> {
+        let key = match button {
+            Button::Keyboard(key) => key,
+            Button::Mouse(MouseButton::Left) => {
+                let mouse = self.mouse;
+                if mouse.0 >= self.view_size[0] - PANEL_WIDTH {
+                    let pressed = buttons(self.view_size).into_iter()
+                        .find(|&(_, rect)| hud::is_inside(rect, mouse));
+                    return pressed.and_then(|(button, _)| self.press(button))
+                }
+                self.painting = true;
+                let tile = self.camera.tile_at(mouse);
+                self.paint(tile);
+                return None
+            },
+            Button::Mouse(MouseButton::Right) => {
+                self.dragging = true;
+                return None
+            },
+            _ => return None,
+        };
+        let button = match key {
+            Key::F => PanelButton::Tool(Tool::Cell(Cell::Floor)),
+            Key::E => PanelButton::Tool(Tool::Cell(Cell::Empty)),
+            Key::W => PanelButton::Tool(Tool::Cell(Cell::Wall)),
+            Key::U => PanelButton::Tool(Tool::Cell(Cell::Upload)),
+            Key::D => PanelButton::Tool(Tool::Cell(Cell::Data)),
+            Key::L => PanelButton::Tool(Tool::Cell(Cell::Slow)),
+            Key::X => PanelButton::Tool(Tool::Cell(Cell::Damage)),
+            Key::C => PanelButton::Tool(Tool::Credits),
+            Key::D1 => PanelButton::Tool(Tool::Unit(0)),
+            Key::D2 => PanelButton::Tool(Tool::Unit(1)),
+            Key::D3 => PanelButton::Tool(Tool::Unit(2)),
+            Key::D4 => PanelButton::Tool(Tool::Unit(3)),
+            Key::T => PanelButton::Team,
+            Key::Left => PanelButton::Resize(-1, 0),
+            Key::Right => PanelButton::Resize(1, 0),
+            Key::Up => PanelButton::Resize(0, -1),
+            Key::Down => PanelButton::Resize(0, 1),
+            Key::S => PanelButton::Save,
+            Key::P => PanelButton::Play,
+            Key::Escape => PanelButton::Back,
+            Key::Equals => {
+                self.camera.zoom_by(ZOOM_STEP);
+                return None
+            },
+            Key::Minus => {
+                self.camera.zoom_by(1.0 / ZOOM_STEP);
+                return None
+            },
+            _ => return None,
+        };
+        self.press(button)
+    }
+
+    pub fn handle_release(&mut self, button: Button) {
+        match button {
+            Button::Mouse(MouseButton::Left) => self.painting = false,
+            Button::Mouse(MouseButton::Right) => self.dragging = false,
+            _ => {},
+        }
+    }
+
+    pub fn handle_mouse(&mut self, x: f64, y: f64) {
+        if self.dragging {
+            self.camera.pan_pixels(self.mouse.0 - x, self.mouse.1 - y);
+        }
+        self.mouse = (x, y);
+        if self.painting && x < self.view_size[0] - PANEL_WIDTH {
+            let tile = self.camera.tile_at(self.mouse);
+            self.paint(tile);
+        }
+    }
+
+    pub fn handle_scroll(&mut self, amount: f64) {
+        self.camera.zoom_by(ZOOM_STEP.powf(amount));
+    }
+
+    fn label(&self, button: PanelButton) -> String {
+        let team = &self.level.teams[self.team as usize];
+        match button {
+            PanelButton::Resize(-1, 0) => "W-".into(),
+            PanelButton::Resize(1, 0) => "W+".into(),
+            PanelButton::Resize(0, -1) => "H-".into(),
+            PanelButton::Resize(..) => "H+".into(),
+            PanelButton::Tool(Tool::Cell(Cell::Upload)) => "Upload".into(),
+            PanelButton::Tool(Tool::Cell(cell)) => cell.name().into(),
+            PanelButton::Tool(Tool::Credits) => "Credits".into(),
+            PanelButton::Tool(Tool::Unit(kind)) => Unit::from_kind(KINDS[kind], (0, 0))
+                                                       .unwrap().name.into(),
+            PanelButton::Team => team.name.clone(),
+            PanelButton::Controller => match team.controller {
+                ControllerKind::Local => "Played locally".into(),
+                ControllerKind::Ai => "Played by AI".into(),
+            },
+            PanelButton::AddTeam => "Add team".into(),
+            PanelButton::Fog => if self.level.fog { "Fog on".into() } else { "Fog off".into() },
+            PanelButton::Save => "Save".into(),
+            PanelButton::Play => "Play test".into(),
+            PanelButton::Back => "Back".into(),
+        }
+    }
+
+    pub fn draw(&mut self, font: &Font, c: &Context, gl: &mut GlGraphics) {
+        self.view_size = c.get_view_size();
+        let size = self.view_size;
+        self.camera.fit(&self.level.grid, size);
+        let world = self.camera.transform(c);
+        clear([0.0, 0.0, 0.0, 1.0], gl);
+
+        let grid = &self.level.grid;
+        for (i, &cell) in grid.grid.iter().enumerate() {
+            let (x, y) = ((i % grid.width) as i16, (i / grid.width) as i16);
+            // Empty tiles are drawn faintly so that the edges of the grid can be seen
+            let alpha = if cell == Cell::Empty { 0.05 } else { 0.3 };
+            grid::draw_tile(cell, (x, y), [1.0, 1.0, 1.0, alpha], world.transform, gl);
+        }
+        for item in &self.level.items {
+            item.draw(world.transform, gl);
+        }
+        for unit in self.level.units.values() {
+            let (x, y) = unit.parts[0];
+            let colour = self.level.teams[unit.team as usize].colour;
+            rectangle([colour[0], colour[1], colour[2], 1.0],
+                      [cell_pos(x), cell_pos(y), CELL_SIZE, CELL_SIZE],
+                      world.transform,
+                      gl);
+            Text::new([0.0, 0.0, 0.0, 1.0]).scale(2.0).align(Align::Centre)
+                .draw(&unit.name[..1], font, cell_pos(x) + CELL_SIZE / 2.0,
+                      cell_pos(y) + CELL_SIZE / 2.0 - 7.0, &world, gl);
+        }
+        let (x, y) = self.camera.tile_at(self.mouse);
+        if grid.is_in_bounds(x, y) && self.mouse.0 < size[0] - PANEL_WIDTH {
+            Rectangle::new_border([1.0, 1.0, 1.0, 0.8], 1.0)
+                .draw([cell_pos(x), cell_pos(y), CELL_SIZE, CELL_SIZE], default_draw_state(),
+                      world.transform, gl);
+        }
+
+        // The side panel
+        let left = size[0] - PANEL_WIDTH + MARGIN;
+        let white = Text::new([1.0, 1.0, 1.0, 1.0]);
+        let grey = Text::new([0.6, 0.6, 0.6, 1.0]);
+        rectangle([0.08, 0.08, 0.08, 1.0], [size[0] - PANEL_WIDTH, 0.0, PANEL_WIDTH, size[1]],
+                  c.transform, gl);
+        let name = self.path.file_stem().map_or("".into(), |s| s.to_string_lossy().into_owned());
+        white.draw(&name, font, left, MARGIN, c, gl);
+        grey.draw(&format!("Size {}x{}", grid.width, grid.height()), font, left, MARGIN + 14.0,
+                  c, gl);
+        for (button, rect) in buttons(size) {
+            let active = match button {
+                PanelButton::Tool(tool) => tool == self.tool,
+                PanelButton::Fog => self.level.fog,
+                _ => false,
+            };
+            let colour = match button {
+                PanelButton::Team => {
+                    let colour = self.level.teams[self.team as usize].colour;
+                    [colour[0], colour[1], colour[2], 1.0]
+                },
+                _ if active => [1.0, 1.0, 1.0, 0.4],
+                _ if hud::is_inside(rect, self.mouse) => [1.0, 1.0, 1.0, 0.25],
+                _ => [1.0, 1.0, 1.0, 0.1],
+            };
+            rectangle(colour, rect, c.transform, gl);
+            let text = if button == PanelButton::Team { [0.0, 0.0, 0.0, 1.0] }
+                       else { [1.0, 1.0, 1.0, 1.0] };
+            Text::new(text).align(Align::Centre)
+                .draw(&self.label(button), font, rect[0] + rect[2] / 2.0, rect[1] + 5.5, c, gl);
+        }
+        let bottom = size[1] - MARGIN - 3.0*(BUTTON_HEIGHT + BUTTON_GAP) - 12.0;
+        grey.draw(&self.message, font, left, bottom, c, gl);
+    }
+}
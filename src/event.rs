@@ -0,0 +1,53 @@
+//! What happens during a game, for anything that wants to know about it.
+//!
+//! `Game::events` is every event so far, in order. Something that wants to hear about them, such
+//! as the level's script or a log, keeps a `Subscription` and polls it for the events it hasn't
+//! seen yet, the same way `net` keeps track of how much of `Game::history` it has sent.
+
+use super::Game;
+use grid::Cell;
+use unit::UnitId;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A unit's head moved one tile.
+    UnitMoved { unit: UnitId, team: u16, from: (i16, i16), to: (i16, i16) },
+    /// A unit lost `amount` cells, to an attack or to the tile it moved onto.
+    UnitDamaged { unit: UnitId, cause: Option<Cause>, amount: usize },
+    /// A unit lost its last cell and was taken out of the game.
+    UnitDestroyed { unit: UnitId, team: u16 },
+    /// A unit was added part way through the game.
+    UnitSpawned { unit: UnitId, team: u16 },
+    /// A tile changed, because of an attack, a unit picking up data or the level's script.
+    TileChanged { pos: (i16, i16), from: Cell, to: Cell, cause: Option<Cause> },
+    RoundStarted { round: u32 },
+    TurnStarted { team: u16, round: u32 },
+    /// The last move or attack was taken back. The events it caused are still in the stream.
+    Undone,
+    GameOver { winner: u16 },
+}
+
+/// The unit that made an event happen by attacking, and the name of the attack it used.
+pub type Cause = (UnitId, &'static str);
+
+/// How far through a game's events a subscriber has got.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Subscription {
+    seen: usize,
+}
+
+impl Subscription {
+    /// A subscription to everything from the start of the game.
+    pub fn new() -> Subscription {
+        Subscription {
+            seen: 0,
+        }
+    }
+
+    /// The events that have happened since the last poll.
+    pub fn poll<'a>(&mut self, game: &'a Game) -> &'a [Event] {
+        let new = &game.events[self.seen..];
+        self.seen = game.events.len();
+        new
+    }
+}
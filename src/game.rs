@@ -1,33 +1,137 @@
-use vec_map::VecMap;
 use std::mem;
-use std::fs::File;
-use std::io::Read;
 use std::path::Path;
-use piston::input::Button;
+use std::collections::{BTreeMap, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use piston::input::{Button, Key, MouseButton};
 use graphics::Context;
 use opengl_graphics::{GlGraphics, Texture};
 
-use super::{Unit, Grid, Controller};
-use controller::{LocalController, DummyController, AiController};
+use super::{Unit, Grid, Controller, CELL_SIZE, CELL_PADDING};
+use unit::UnitId;
+use controller::DummyController;
+use level::{Level, TurnLimit, Goal};
+use anim::{self, Animations};
+use camera::Camera;
+use event::{Event, Subscription};
+use log::CombatLog;
+use item::Item;
+use hud;
+use keys::{Bindings, Control};
+use pad::{Pad, PadEvent, PadButton, Stick};
+use script::{Trigger, Effect};
+use text::{Font, Text, Align};
+
+/// How many frames the banner announcing a new turn stays on screen for.
+const BANNER_FRAMES: u64 = 45;
+/// How many frames messages from the level stay on screen for.
+const MESSAGE_FRAMES: u64 = 240;
+/// How much each step of zooming in makes things bigger.
+const ZOOM_STEP: f64 = 1.25;
+/// How many tiles the view moves each time a pan key is pressed.
+const PAN_STEP: f64 = 3.0;
+/// How close to the edge of the grid's part of the window the mouse has to be to scroll it.
+const EDGE: f64 = 10.0;
+/// How many pixels the view scrolls each frame when the mouse is at the edge.
+const EDGE_SPEED: f64 = 8.0;
 
 pub struct Game {
     pub grid: Grid,
-    pub units: VecMap<Unit>,
+    pub units: BTreeMap<UnitId, Unit>,
+    /// The id the next unit to be added gets.
+    next_id: u32,
+    /// The items still waiting to be picked up.
+    pub items: Vec<Item>,
     pub frame: u64,
     pub mouse: (f64, f64),
-    pub selected_idx: Option<usize>,
+    pub selected_id: Option<UnitId>,
     pub teams: Vec<Team>,
     pub current_team: u16,
     pub textures: Vec<Texture>,
+    pub font: Font,
+    /// The keys used by local players.
+    pub bindings: Bindings,
+    pub pad: Pad,
     pub done: bool,
     pub undo: Vec<UndoState>,
-    curr_units: Vec<usize>,
+    /// The frame on which the current team's turn began.
+    pub turn_frame: u64,
+    /// The current round, counting from 1 once the first turn has started. A round is over once
+    /// every team has had a turn.
+    pub round: u32,
+    pub limit: Option<TurnLimit>,
+    /// The level's script: what happens when. Triggers are taken out once they have gone off.
+    pub triggers: Vec<(Trigger, Effect)>,
+    /// How far through `events` the script has got.
+    script: Subscription,
+    /// The team a script has declared the winner.
+    pub victor: Option<u16>,
+    /// How many units have been added since the game started, so that anything keeping track of
+    /// the units can tell when there are new ones.
+    pub spawned: u64,
+    /// The last message from the level, and the frame it was shown on.
+    message: Option<(String, u64)>,
+    /// Every action performed so far, in order.
+    pub history: Vec<Action>,
+    /// Everything that has happened so far, in order (see `event`).
+    pub events: Vec<Event>,
+    /// Whether the game has been won, and said so in `events`.
+    over: bool,
+    log: CombatLog,
+    /// Whether teams can only see the tiles near their own units.
+    pub fog: bool,
+    /// For each team, which tiles it can currently see.
+    pub visibility: Vec<Vec<bool>>,
+    /// The team whose view of the grid is drawn: the current team if it is played locally, or
+    /// the last one that was otherwise.
+    pub viewer: u16,
+    pub anims: Animations,
+    pub camera: Camera,
+    /// Whether the mouse is in the window.
+    pub hovering: bool,
+    /// Whether the view is being dragged around with the right mouse button.
+    pub dragging: bool,
+    /// The tile the camera last started following.
+    followed: Option<(i16, i16)>,
+    /// The size of the window when it was last drawn.
+    pub view_size: [f64; 2],
+    curr_units: Vec<UnitId>,
+}
+
+/// Something a team can do on its turn. All of a team's input goes through `Game::perform`, so
+/// that it can be recorded and replayed elsewhere.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    Select(UnitId),
+    SelectNext,
+    /// Moves the selected unit, or its attack target if it is attacking.
+    Move(i16, i16),
+    /// Enters or leaves attack mode with the given attack of the selected unit.
+    Attack(u16),
+    Target(i16, i16),
+    Fire,
+    /// Uses up the selected unit's turn without doing anything.
+    Wait,
+    EndTurn,
+    Undo,
+}
+
+/// Something for whatever is running the game to do, asked for by one of the keys that work
+/// whoever's turn it is.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Pause,
+    Restart,
+    QuitToMenu,
 }
 
 pub struct UndoState {
     grid: Grid,
-    units: VecMap<Unit>,
-    selected_idx: Option<usize>,
+    units: BTreeMap<UnitId, Unit>,
+    items: Vec<Item>,
+    /// Each team's credits.
+    credits: Vec<u32>,
+    selected_id: Option<UnitId>,
 }
 
 impl Game {
@@ -35,46 +139,60 @@ impl Game {
         self.undo.push(UndoState {
             grid: self.grid.clone(),
             units: self.units.clone(),
-            selected_idx: self.selected_idx,
+            items: self.items.clone(),
+            credits: self.teams.iter().map(|team| team.credits).collect(),
+            selected_id: self.selected_id,
         });
     }
 
     pub fn save_with(&mut self, unit: Unit) {
-        let idx = *self.curr_units.last().unwrap();
+        let id = *self.curr_units.last().unwrap();
         let mut units = self.units.clone();
-        units.insert(idx, unit);
+        units.insert(id, unit);
         self.undo.push(UndoState {
             grid: self.grid.clone(),
             units: units,
-            selected_idx: self.selected_idx,
+            items: self.items.clone(),
+            credits: self.teams.iter().map(|team| team.credits).collect(),
+            selected_id: self.selected_id,
         });
     }
 
     pub fn undo(&mut self) {
-        if let Some(UndoState { grid, units, selected_idx }) = self.undo.pop() {
+        if let Some(UndoState { grid, units, items, credits, selected_id }) = self.undo.pop() {
             self.grid = grid;
             self.units = units;
-            self.selected_idx = selected_idx;
+            self.items = items;
+            for (team, credits) in self.teams.iter_mut().zip(credits) {
+                team.credits = credits;
+            }
+            self.selected_id = selected_id;
             self.done = false;
+            self.anims.clear();
+            self.emit(Event::Undone);
         }
     }
 
     pub fn sample() -> Game {
-        let mut f = File::open(Path::new("levels/test.sunrise")).unwrap();
-        let mut s = String::new();
-        f.read_to_string(&mut s).unwrap();
-        let (grid, units) = Grid::from_string(&s);
+        Game::new(Level::from_path("levels/test.sunrise"))
+    }
 
-        Game {
+    pub fn new(level: Level) -> Game {
+        let Level { grid, units, items, teams, fog, limit, triggers } = level;
+
+        // Units get their ids in the order the level lists them
+        let next_id = units.keys().max().map_or(0, |idx| idx as u32 + 1);
+        let mut game = Game {
             grid: grid,
-            units: units,
+            units: units.into_iter().map(|(idx, unit)| (UnitId(idx as u32), unit)).collect(),
+            next_id: next_id,
+            items: items,
             frame: 0,
             mouse: (0.0, 0.0),
-            selected_idx: None,
-            teams: vec![
-                Team { name: "Player".into(), controller: Box::new(LocalController) },
-                Team { name: "Enemy".into(), controller: Box::new(AiController::new()) },
-            ],
+            selected_id: None,
+            teams: teams.into_iter().map(|spec| {
+                Team::new(spec.name, spec.controller.create(), spec.colour)
+            }).collect(),
             current_team: 0,
             textures: vec![
                 Texture::from_memory_alpha(&[], 0, 0).unwrap(),
@@ -83,28 +201,76 @@ impl Game {
                 Texture::from_path(&Path::new("./assets/warden.png")).unwrap(),
                 Texture::from_path(&Path::new("./assets/crosshair.png")).unwrap(),
             ],
+            font: Font::from_path("./assets/font.txt"),
+            bindings: Bindings::default(),
+            pad: Pad::new(),
             done: false,
+            turn_frame: 0,
+            round: 0,
+            limit: limit,
+            triggers: triggers,
+            script: Subscription::new(),
+            victor: None,
+            spawned: 0,
+            message: None,
+            history: vec![],
+            events: vec![],
+            over: false,
+            log: CombatLog::new(),
+            fog: fog,
+            visibility: vec![],
+            viewer: 0,
+            anims: Animations::new(),
+            camera: Camera::new(),
+            hovering: false,
+            dragging: false,
+            followed: None,
+            view_size: [0.0, 0.0],
             curr_units: vec![],
             undo: vec![],
-        }
+        };
+        game.update_visibility();
+        game
+    }
+
+    pub fn unit(&self, id: UnitId) -> Option<&Unit> {
+        self.units.get(&id)
+    }
+
+    pub fn unit_mut(&mut self, id: UnitId) -> Option<&mut Unit> {
+        self.units.get_mut(&id)
+    }
+
+    pub fn selected_unit(&self) -> Option<&Unit> {
+        self.selected_id.and_then(|id| self.unit(id))
     }
 
-    pub fn for_unit<F>(&mut self, idx: usize, f: F) where F: FnOnce(&mut Unit, &mut Game) {
-        let mut unit = self.units.remove(&idx).unwrap();
-        self.curr_units.push(idx);
+    /// The id of the unit taken out of the game by `for_unit` or `for_each_unit`, while `f` is
+    /// running.
+    pub fn acting_unit(&self) -> Option<UnitId> {
+        self.curr_units.last().cloned()
+    }
+
+    /// Calls `f` with the unit with the given id taken out of the game, if there is one.
+    pub fn for_unit<F>(&mut self, id: UnitId, f: F) where F: FnOnce(&mut Unit, &mut Game) {
+        let mut unit = match self.units.remove(&id) {
+            Some(unit) => unit,
+            None => return,
+        };
+        self.curr_units.push(id);
         f(&mut unit, self);
         self.curr_units.pop();
-        self.units.insert(idx, unit);
+        self.units.insert(id, unit);
     }
 
-    pub fn for_each_unit<F>(&mut self, mut f: F) where F: FnMut(&mut Unit, &mut Game, usize) {
-        let iter = self.units.keys().collect::<Vec<_>>().into_iter();
-        for idx in iter {
-            let mut unit = self.units.remove(&idx).unwrap();
-            self.curr_units.push(idx);
-            f(&mut unit, self, idx);
+    pub fn for_each_unit<F>(&mut self, mut f: F) where F: FnMut(&mut Unit, &mut Game, UnitId) {
+        let ids: Vec<_> = self.units.keys().cloned().collect();
+        for id in ids {
+            let mut unit = self.units.remove(&id).unwrap();
+            self.curr_units.push(id);
+            f(&mut unit, self, id);
             self.curr_units.pop();
-            self.units.insert(idx, unit);
+            self.units.insert(id, unit);
         }
     }
 
@@ -114,13 +280,15 @@ impl Game {
         self.grid = grid;
     }
 
-    pub fn for_current_team<F>(&mut self, f: F) where F: FnOnce(&mut Team, &mut Game) {
+    /// Calls `f` with the current team's controller taken out of the game. The rest of the team
+    /// stays where it is, so that its credits and name can still be used in the meantime.
+    pub fn for_current_controller<F>(&mut self, f: F)
+            where F: FnOnce(&mut Box<Controller>, &mut Game) {
         let cur = self.current_team as usize;
-        let mut team = mem::replace(&mut self.teams[cur],
-                                    Team { name: "Dummy".into(),
-                                           controller: Box::new(DummyController) });
-        f(&mut team, self);
-        self.teams[cur] = team;
+        let mut controller = mem::replace(&mut self.teams[cur].controller,
+                                          Box::new(DummyController));
+        f(&mut controller, self);
+        self.teams[cur].controller = controller;
     }
 
     pub fn is_valid(&self, x: i16, y: i16) -> bool {
@@ -128,42 +296,203 @@ impl Game {
             && self.units.values().all(|a| !a.occupies(x, y))
     }
 
-    pub fn select(&mut self, unit_idx: usize) {
+    pub fn select(&mut self, id: UnitId) {
+        if !self.units.contains_key(&id) { return }
         self.deselect();
-        self.selected_idx = Some(unit_idx);
-        self.units[unit_idx].selected = true;
-        self.for_unit(unit_idx, |unit, game| {
+        self.selected_id = Some(id);
+        self.units.get_mut(&id).unwrap().selected = true;
+        self.for_unit(id, |unit, game| {
             unit.highlight(game);
         });
         self.done = false;
     }
 
     pub fn select_team(&mut self, team_idx: u16) {
+        // Coming back round to the same team or an earlier one starts a new round, as does the
+        // very first turn
+        let new_round = self.round == 0 || team_idx <= self.current_team;
         self.undo.clear();
         self.for_each_unit(|unit, _, _| {
             unit.moves = unit.move_limit;
             unit.has_attacked = false;
             unit.attack = None;
         });
-        let idx = self.units.iter().find(|&(_, ref x)| x.team == team_idx).unwrap().0;
-        self.select(idx);
+        self.deselect();
+        self.clear_highlight();
+        let id = self.units.iter().find(|&(_, x)| x.team == team_idx).map(|(&id, _)| id);
+        if let Some(id) = id {
+            self.select(id);
+        }
         self.current_team = team_idx;
+        self.turn_frame = self.frame;
         self.done = false;
+        if new_round {
+            self.round += 1;
+            let round = self.round;
+            self.emit(Event::RoundStarted { round: round });
+        }
+        let round = self.round;
+        self.emit(Event::TurnStarted { team: team_idx, round: round });
+        self.run_script();
+        self.check_over();
+        self.update_log();
+        self.update_visibility();
+    }
+
+    pub fn emit(&mut self, event: Event) {
+        self.events.push(event);
+    }
+
+    /// Sets off the level's triggers for whatever has happened since the script last looked,
+    /// and makes their effects happen, along with those of any triggers the effects set off in
+    /// turn. There's no undoing past them.
+    fn run_script(&mut self) {
+        loop {
+            let mut script = self.script;
+            let happened: Vec<_> = script.poll(self).iter()
+                .filter_map(|event| Trigger::from_event(event))
+                .collect();
+            self.script = script;
+
+            let mut effects = vec![];
+            for happened in happened {
+                let triggers = mem::replace(&mut self.triggers, vec![]);
+                for (trigger, effect) in triggers {
+                    if trigger == happened {
+                        effects.push(effect);
+                    } else {
+                        self.triggers.push((trigger, effect));
+                    }
+                }
+            }
+            if effects.is_empty() { return }
+            for effect in effects {
+                effect.apply(self);
+            }
+            self.undo.clear();
+        }
+    }
+
+    fn update_log(&mut self) {
+        let mut log = mem::replace(&mut self.log, CombatLog::dummy());
+        log.update(self);
+        self.log = log;
+    }
+
+    /// Says so in `events` the first time the game has a winner.
+    fn check_over(&mut self) {
+        if self.over { return }
+        if let Some(winner) = self.winner() {
+            self.over = true;
+            self.emit(Event::GameOver { winner: winner });
+        }
+    }
+
+    /// Adds a unit to the game with a new id, returning the id.
+    pub fn add_unit(&mut self, unit: Unit) -> UnitId {
+        let id = UnitId(self.next_id);
+        self.next_id += 1;
+        self.units.insert(id, unit);
+        id
+    }
+
+    /// Adds a one-tile unit to the game part way through, returning its id. If another unit
+    /// is in the way, it goes on the nearest free tile that could be walked to from where it
+    /// was meant to go, and if there isn't one, or it was meant to go somewhere units can't
+    /// stand, it isn't added at all. Units that have been added can't be undone, so neither can
+    /// anything before them.
+    pub fn spawn(&mut self, mut unit: Unit) -> Option<UnitId> {
+        let start = unit.parts[0];
+        if !self.grid.is_valid(start.0, start.1) { return None }
+
+        // Search outwards from the starting tile for one without a unit on it
+        let width = self.grid.width;
+        let mut seen = vec![false; self.grid.grid.len()];
+        let mut queue = VecDeque::new();
+        seen[start.0 as usize + width*start.1 as usize] = true;
+        queue.push_back(start);
+        let mut pos = None;
+        while let Some((x, y)) = queue.pop_front() {
+            if self.is_valid(x, y) {
+                pos = Some((x, y));
+                break
+            }
+            for &(dx, dy) in [(0, -1), (1, 0), (0, 1), (-1, 0)].iter() {
+                let (nx, ny) = (x + dx, y + dy);
+                if self.grid.is_valid(nx, ny) && !seen[nx as usize + width*ny as usize] {
+                    seen[nx as usize + width*ny as usize] = true;
+                    queue.push_back((nx, ny));
+                }
+            }
+        }
+        let pos = match pos {
+            Some(pos) => pos,
+            None => return None,
+        };
+
+        unit.parts.clear();
+        unit.parts.push_back(pos);
+        let team = unit.team;
+        let id = self.add_unit(unit);
+        self.emit(Event::UnitSpawned { unit: id, team: team });
+        self.undo.clear();
+        self.spawned += 1;
+        self.anims.add(self.frame, anim::Effect::Flash { pos: pos });
+        self.update_visibility();
+        Some(id)
+    }
+
+    /// Shows a message across the top of the grid for a while.
+    pub fn show_message(&mut self, text: &str) {
+        self.message = Some((text.into(), self.frame));
+    }
+
+    /// Returns whether the given team has any units left.
+    pub fn team_alive(&self, team_idx: u16) -> bool {
+        self.units.values().any(|x| x.team == team_idx)
     }
 
+    /// Returns the team that has won, once every other team has lost all of its units, the
+    /// level's turn limit has run out or its script has declared a winner.
+    pub fn winner(&self) -> Option<u16> {
+        if self.victor.is_some() { return self.victor }
+        let mut alive = (0..self.teams.len() as u16).filter(|&idx| self.team_alive(idx));
+        match (alive.next(), alive.next()) {
+            (Some(idx), None) => return Some(idx),
+            _ => {},
+        }
+        match self.limit {
+            Some(limit) if self.round > limit.rounds => match limit.goal {
+                Goal::Survive if self.team_alive(limit.team) => Some(limit.team),
+                Goal::Survive => None,
+                // The first of the other teams still standing takes the win
+                Goal::Win => {
+                    (0..self.teams.len() as u16)
+                        .find(|&idx| idx != limit.team && self.team_alive(idx))
+                },
+            },
+            _ => None,
+        }
+    }
+
+    /// Passes the turn on to the next team that still has units left.
     pub fn next_team(&mut self) {
-        let idx = self.current_team;
         let len = self.teams.len() as u16;
-        if let Some(idx) = self.selected_idx {
-            self.for_unit(idx, |unit, game| {
+        if let Some(id) = self.selected_id {
+            self.for_unit(id, |unit, game| {
                 unit.leave_attack(game);
             });
         }
-        self.select_team((idx + 1) % len);
+        let mut idx = self.current_team;
+        loop {
+            idx = (idx + 1) % len;
+            if idx == self.current_team || self.team_alive(idx) { break }
+        }
+        self.select_team(idx);
     }
 
-    pub fn attack(&mut self, unit_idx: usize, attack: u16) {
-        self.for_unit(unit_idx, |unit, game| {
+    pub fn attack(&mut self, id: UnitId, attack: u16) {
+        self.for_unit(id, |unit, game| {
             if unit.attack.is_some() {
                 game.undo.pop();
                 unit.leave_attack(game);
@@ -174,16 +503,17 @@ impl Game {
         });
     }
 
-    pub fn fire(&mut self, unit_idx: usize) {
-        self.for_unit(unit_idx, |unit, game| {
+    pub fn fire(&mut self, id: UnitId) {
+        self.for_unit(id, |unit, game| {
             unit.fire(game);
         });
-        if self.units[unit_idx].parts.len() == 0 {
-            self.units.remove(&unit_idx);
-            let idx = self.units.iter().find(|&(_, ref x)| x.is_player(self)).map(|(i, _)| i);
-            if let Some(idx) = idx {
-                self.select(idx);
-                self.for_unit(idx, |unit, game| {
+        if self.unit(id).map_or(false, |unit| unit.parts.len() == 0) {
+            let unit = self.units.remove(&id).unwrap();
+            self.emit(Event::UnitDestroyed { unit: id, team: unit.team });
+            let next = self.units.iter().find(|&(_, x)| x.is_player(self)).map(|(&id, _)| id);
+            if let Some(next) = next {
+                self.select(next);
+                self.for_unit(next, |unit, game| {
                     unit.highlight(game);
                 });
             }
@@ -191,26 +521,134 @@ impl Game {
     }
 
     pub fn select_next(&mut self) {
-        if let Some(idx) = self.selected_idx {
-            if self.units[idx].attack.is_none() {
-                let mut keys: Vec<_> = self.units.keys().collect();
-                keys.sort();
-                let mut idx = keys.iter().position(|x| *x == idx).unwrap();
-                loop {
-                    idx += 1;
-                    idx %= keys.len();
-                    if self.units[keys[idx]].is_player(self) { break }
+        if let Some(id) = self.selected_id {
+            if self.unit(id).map_or(false, |unit| unit.attack.is_none()) {
+                // The units come in order of their ids, so the next one round is either the
+                // first after this one or the first of all
+                let next = {
+                    let ours = self.units.iter().filter(|&(_, x)| x.is_player(self))
+                                                .map(|(&id, _)| id);
+                    let ids: Vec<_> = ours.collect();
+                    ids.iter().cloned().find(|&other| other > id).or(ids.first().cloned())
+                };
+                if let Some(next) = next {
+                    self.select(next);
                 }
-                self.select(keys[idx] as usize);
             }
         }
     }
 
     pub fn deselect(&mut self) {
-        if let Some(idx) = self.selected_idx {
-            self.units[idx].selected = false;
+        if let Some(unit) = self.selected_id.and_then(|id| self.units.get_mut(&id)) {
+            unit.selected = false;
         }
-        self.selected_idx = None;
+        self.selected_id = None;
+    }
+
+    /// Whether `action` makes sense in the current state. Actions from elsewhere, such as the
+    /// other side of a networked game, are checked with this before they are performed.
+    pub fn can_perform(&self, action: Action) -> bool {
+        let selected = self.selected_unit();
+        let attacking = selected.map_or(false, |unit| unit.attack.is_some());
+        match action {
+            Action::Select(id) => self.unit(id).is_some(),
+            Action::Attack(attack) => selected.map_or(false, |unit| {
+                unit.attack.is_some() || (attack as usize) < unit.attacks.len()
+            }),
+            Action::Target(x, y) => attacking && self.grid.is_in_bounds(x, y),
+            Action::Fire => {
+                attacking && self.grid.attack_loc.is_some() && !self.undo.is_empty()
+            },
+            Action::SelectNext | Action::Move(..) | Action::Wait | Action::EndTurn
+            | Action::Undo => true,
+        }
+    }
+
+    pub fn perform(&mut self, action: Action) {
+        self.history.push(action);
+        match action {
+            Action::Select(id) => self.select(id),
+            Action::SelectNext => self.select_next(),
+            Action::Move(dx, dy) => if let Some(id) = self.selected_id {
+                self.for_unit(id, |unit, game| {
+                    unit.relocate(game, dx, dy);
+                });
+            },
+            Action::Attack(attack) => if let Some(id) = self.selected_id {
+                self.attack(id, attack);
+            },
+            Action::Target(x, y) => self.grid.attack_loc = Some((x, y)),
+            Action::Fire => if let Some(id) = self.selected_id {
+                self.fire(id);
+            },
+            Action::Wait => if let Some(id) = self.selected_id {
+                if let Some(unit) = self.unit_mut(id) {
+                    unit.has_attacked = true;
+                    unit.moves = 0;
+                }
+            },
+            Action::EndTurn => self.next_team(),
+            Action::Undo => self.undo(),
+        }
+        self.run_script();
+        self.check_over();
+        self.update_log();
+        self.update_visibility();
+    }
+
+    /// Recalculates which tiles each team can see from its units.
+    pub fn update_visibility(&mut self) {
+        let width = self.grid.width as i16;
+        let mut visibility = vec![vec![false; self.grid.grid.len()]; self.teams.len()];
+        for unit in self.units.values() {
+            let seen = &mut visibility[unit.team as usize];
+            let sight = unit.sight as i16;
+            for &(ux, uy) in &unit.parts {
+                for dy in -sight..sight + 1 {
+                    for dx in -sight..sight + 1 {
+                        let (x, y) = (ux + dx, uy + dy);
+                        if dx.abs() + dy.abs() <= sight && self.grid.is_in_bounds(x, y) {
+                            seen[(x + width*y) as usize] = true;
+                        }
+                    }
+                }
+            }
+        }
+        self.visibility = visibility;
+    }
+
+    /// Returns whether the given team can see the tile at `(x, y)`.
+    pub fn can_see(&self, team: u16, x: i16, y: i16) -> bool {
+        if !self.fog { return true }
+        self.grid.is_in_bounds(x, y)
+            && self.visibility[team as usize][x as usize + self.grid.width*y as usize]
+    }
+
+    /// Returns whether the tile at the given index into the grid should be drawn. Unlike
+    /// `can_see`, this works inside `for_grid`.
+    pub fn is_shown(&self, idx: usize) -> bool {
+        !self.fog || self.visibility[self.viewer as usize][idx]
+    }
+
+    /// A hash of everything that affects the outcome of the game, used to check that networked
+    /// games haven't gone out of sync.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.grid.grid.hash(&mut hasher);
+        self.current_team.hash(&mut hasher);
+        self.round.hash(&mut hasher);
+        for (id, unit) in &self.units {
+            id.hash(&mut hasher);
+            unit.parts.hash(&mut hasher);
+            unit.team.hash(&mut hasher);
+            unit.moves.hash(&mut hasher);
+            unit.has_attacked.hash(&mut hasher);
+        }
+        self.items.hash(&mut hasher);
+        for team in &self.teams {
+            team.credits.hash(&mut hasher);
+        }
+        hasher.finish()
     }
 
     pub fn clear_highlight(&mut self) {
@@ -219,47 +657,251 @@ impl Game {
         self.grid.player_pos = None;
     }
 
-    pub fn handle_press(&mut self, args: Button) {
+    /// Handles the keys that work on every team's turn, including the AI's, and passes any other
+    /// input on to the current team's controller.
+    pub fn handle_press(&mut self, args: Button) -> Option<Command> {
         match args {
-            // TODO: handle pause, etc.
+            Button::Keyboard(Key::Escape) => Some(Command::Pause),
+            Button::Keyboard(Key::R) => Some(Command::Restart),
+            Button::Keyboard(Key::M) => Some(Command::QuitToMenu),
+            Button::Keyboard(Key::Equals) => {
+                self.camera.zoom_by(ZOOM_STEP);
+                None
+            },
+            Button::Keyboard(Key::Minus) => {
+                self.camera.zoom_by(1.0 / ZOOM_STEP);
+                None
+            },
+            Button::Keyboard(k) if self.pan_control(self.bindings.control(k)) => None,
+            Button::Mouse(MouseButton::Right) => {
+                self.dragging = true;
+                None
+            },
             args => {
-                self.for_current_team(|team, game| {
-                    team.controller.handle_press(game, args);
+                self.for_current_controller(|controller, game| {
+                    controller.handle_press(game, args);
                 });
+                None
             },
         }
     }
 
+    /// Like `handle_press`, but for gamepads.
+    pub fn handle_pad(&mut self, event: PadEvent) -> Option<Command> {
+        if event == PadEvent::Press(PadButton::Start) {
+            return Some(Command::Pause)
+        }
+        let control = self.pad.control(event);
+        // The right stick moves attack targets, or the view when there aren't any
+        if let PadEvent::Stick(Stick::Right, _, _) = event {
+            if self.grid.attack_loc.is_none() {
+                self.pan_towards(control);
+                return None
+            }
+        }
+        if let Some(control) = control {
+            self.for_current_controller(|controller, game| {
+                controller.handle_control(game, control);
+            });
+        }
+        None
+    }
+
+    /// Pans the view in the direction of `control` if it is one of the movement controls, as
+    /// given by a stick.
+    pub fn pan_towards(&mut self, control: Option<Control>) {
+        let pan = match control {
+            Some(Control::Up) => Some(Control::Pan(0, -1)),
+            Some(Control::Down) => Some(Control::Pan(0, 1)),
+            Some(Control::Left) => Some(Control::Pan(-1, 0)),
+            Some(Control::Right) => Some(Control::Pan(1, 0)),
+            _ => None,
+        };
+        self.pan_control(pan);
+    }
+
+    /// Pans the view if `control` is one of the pan controls, returning whether it was.
+    pub fn pan_control(&mut self, control: Option<Control>) -> bool {
+        match control {
+            Some(Control::Pan(dx, dy)) => {
+                let step = PAN_STEP * (CELL_SIZE + CELL_PADDING);
+                self.camera.pan(dx as f64 * step, dy as f64 * step);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    pub fn handle_release(&mut self, args: Button) {
+        if args == Button::Mouse(MouseButton::Right) {
+            self.dragging = false;
+        }
+    }
+
+    /// Zooms in or out by how far the mouse wheel was scrolled.
+    pub fn handle_scroll(&mut self, amount: f64) {
+        self.camera.zoom_by(ZOOM_STEP.powf(amount));
+    }
+
     pub fn handle_mouse(&mut self, x: f64, y: f64) {
+        if self.dragging {
+            self.camera.pan_pixels(self.mouse.0 - x, self.mouse.1 - y);
+        }
         self.mouse = (x, y);
-        self.for_current_team(|team, game| {
-            team.controller.handle_mouse(game);
+        self.hovering = true;
+        self.for_current_controller(|controller, game| {
+            controller.handle_mouse(game);
         });
     }
 
+    fn update_viewer(&mut self) {
+        if self.teams[self.current_team as usize].is_local() {
+            self.viewer = self.current_team;
+        } else if !self.teams[self.viewer as usize].is_local() {
+            if let Some(idx) = self.teams.iter().position(Team::is_local) {
+                self.viewer = idx as u16;
+            }
+        }
+    }
+
+    /// Scrolls the view when the mouse is at its edges, and follows the selected unit or its
+    /// target.
+    fn update_camera(&mut self) {
+        let (x, y) = self.mouse;
+        let right = self.view_size[0] - hud::PANEL_WIDTH;
+        if self.hovering && !self.dragging && x < right {
+            let dx = if x < EDGE { -1.0 } else if x > right - EDGE { 1.0 } else { 0.0 };
+            let dy = if y < EDGE { -1.0 } else if y > self.view_size[1] - EDGE { 1.0 } else { 0.0 };
+            if dx != 0.0 || dy != 0.0 {
+                self.camera.pan_pixels(dx * EDGE_SPEED, dy * EDGE_SPEED);
+            }
+        }
+
+        let followed = self.selected_unit()
+                           .map(|unit| self.grid.attack_loc.unwrap_or(unit.parts[0]));
+        if followed != self.followed {
+            self.followed = followed;
+            if let Some((x, y)) = followed {
+                if self.can_see(self.viewer, x, y) {
+                    self.camera.follow((x, y));
+                }
+            }
+        }
+        self.camera.update();
+    }
+
     pub fn handle_frame(&mut self) {
         self.frame += 1;
-        self.for_current_team(|team, game| {
-            team.controller.handle_frame(game);
+        self.anims.update(self.frame);
+        self.update_viewer();
+        self.update_camera();
+        self.for_current_controller(|controller, game| {
+            controller.handle_frame(game);
         });
     }
 
     pub fn draw(&mut self, c: &Context, gl: &mut GlGraphics) {
+        self.draw_world(c, gl);
+        hud::draw(self, c, gl);
+        self.log.draw(self, c, gl);
+        self.draw_message(c, gl);
+        self.draw_banner(c, gl);
+    }
+
+    /// Draws the grid and everything on it, without the side panel.
+    pub fn draw_world(&mut self, c: &Context, gl: &mut GlGraphics) {
         use graphics::*;
+        self.view_size = c.get_view_size();
+        self.camera.fit(&self.grid, self.view_size);
+        let world = self.camera.transform(c);
         clear([0.0, 0.0, 0.0, 1.0], gl);
         self.for_grid(|grid, game| {
-            grid.draw(game, &c, gl);
+            grid.draw(game, &world, gl);
         });
+        let width = self.grid.width;
+        for item in &self.items {
+            if self.is_shown(item.pos.0 as usize + item.pos.1 as usize * width) {
+                item.draw(world.transform, gl);
+            }
+        }
         self.for_each_unit(|unit, game, _| {
-            unit.draw(game, &c, gl);
+            unit.draw(game, &world, gl);
         });
+        self.anims.draw(self, &world, gl);
         self.for_grid(|grid, game| {
-            grid.draw_overlay(game, &c, gl);
+            grid.draw_overlay(game, &world, gl);
         });
     }
+
+    /// Draws a bar in the current team's colour across the screen at the start of each turn.
+    fn draw_banner(&self, c: &Context, gl: &mut GlGraphics) {
+        use graphics::rectangle;
+        let elapsed = self.frame - self.turn_frame;
+        if elapsed >= BANNER_FRAMES { return }
+
+        let size = c.get_view_size();
+        let team = &self.teams[self.current_team as usize];
+        let colour = team.colour;
+        let alpha = 0.8 * (1.0 - elapsed as f32 / BANNER_FRAMES as f32);
+        rectangle([colour[0], colour[1], colour[2], alpha],
+                  [0.0, size[1] / 2.0 - 20.0, size[0], 40.0],
+                  c.transform,
+                  gl);
+        Text::new([0.0, 0.0, 0.0, alpha / 0.8]).scale(3.0).align(Align::Centre)
+            .draw(&format!("{}'s turn", team.name), &self.font,
+                  size[0] / 2.0, size[1] / 2.0 - 10.5, c, gl);
+    }
+
+    /// Draws the last message from the level across the top of the grid, fading it out at the
+    /// end.
+    fn draw_message(&self, c: &Context, gl: &mut GlGraphics) {
+        use graphics::rectangle;
+        let (text, frame) = match self.message {
+            Some((ref text, frame)) => (text, frame),
+            None => return,
+        };
+        let elapsed = self.frame - frame;
+        if elapsed >= MESSAGE_FRAMES { return }
+
+        let width = c.get_view_size()[0] - hud::PANEL_WIDTH;
+        let fade = MESSAGE_FRAMES / 4;
+        let alpha = if elapsed < MESSAGE_FRAMES - fade {
+            1.0
+        } else {
+            (MESSAGE_FRAMES - elapsed) as f32 / fade as f32
+        };
+        rectangle([0.0, 0.0, 0.0, 0.7 * alpha], [0.0, 10.0, width, 30.0], c.transform, gl);
+        Text::new([1.0, 1.0, 1.0, alpha]).scale(2.0).align(Align::Centre)
+            .draw(text, &self.font, width / 2.0, 18.0, c, gl);
+    }
 }
 
 pub struct Team {
     pub name: String,
+    pub colour: [f32; 3],
+    /// The credits the team has picked up so far.
+    pub credits: u32,
     controller: Box<Controller>,
 }
+
+impl Team {
+    pub fn new(name: String, controller: Box<Controller>, colour: [f32; 3]) -> Team {
+        Team {
+            name: name,
+            colour: colour,
+            credits: 0,
+            controller: controller,
+        }
+    }
+
+    pub fn is_local(&self) -> bool {
+        self.controller.is_local_controlled()
+    }
+
+    /// Replaces the team's controller with one made from the old one.
+    pub fn wrap_controller<F>(&mut self, f: F)
+            where F: FnOnce(Box<Controller>) -> Box<Controller> {
+        let inner = mem::replace(&mut self.controller, Box::new(DummyController));
+        self.controller = f(inner);
+    }
+}
@@ -1,13 +1,102 @@
 use super::{Game, Unit, CELL_SIZE, CELL_PADDING, cell_pos};
 use std::ops::{Index, IndexMut};
 use graphics::Context;
+use graphics::math::Matrix2d;
 use opengl_graphics::GlGraphics;
 use vec_map::VecMap;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Cell {
     Empty,
     Floor,
+    /// Can't be moved onto, and attacks can't reach past it.
+    Wall,
+    /// Floor that units can be uploaded onto before a battle.
+    Upload,
+    /// Floor with data on it. The first unit to move onto it collects the data, which lets it
+    /// grow one tile longer, and leaves plain floor behind.
+    Data,
+    /// Moving onto it uses up the rest of the unit's moves.
+    Slow,
+    /// Moving onto it costs the unit the last tile of its tail.
+    Damage,
+}
+
+/// Every kind of cell, in the order the level editor offers them.
+pub const CELLS: [Cell; 7] = [Cell::Floor, Cell::Empty, Cell::Wall, Cell::Upload, Cell::Data,
+                              Cell::Slow, Cell::Damage];
+
+impl Cell {
+    /// The character used for the cell in level files.
+    pub fn glyph(self) -> char {
+        match self {
+            Cell::Empty => ' ',
+            Cell::Floor => '#',
+            Cell::Wall => '%',
+            Cell::Upload => '@',
+            Cell::Data => '$',
+            Cell::Slow => '~',
+            Cell::Damage => '!',
+        }
+    }
+
+    pub fn from_glyph(c: char) -> Option<Cell> {
+        CELLS.iter().cloned().find(|cell| cell.glyph() == c)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Cell::Empty => "Empty",
+            Cell::Floor => "Floor",
+            Cell::Wall => "Wall",
+            Cell::Upload => "Upload zone",
+            Cell::Data => "Data",
+            Cell::Slow => "Slow",
+            Cell::Damage => "Damaging",
+        }
+    }
+
+    /// Whether units can move onto the cell.
+    pub fn is_walkable(self) -> bool {
+        self != Cell::Empty && self != Cell::Wall
+    }
+}
+
+/// Draws the tile at `(x, y)`, with its floor in the given colour.
+pub fn draw_tile(cell: Cell, (x, y): (i16, i16), colour: [f32; 4], transform: Matrix2d,
+                 gl: &mut GlGraphics) {
+    use graphics::*;
+
+    let (left, top) = (cell_pos(x), cell_pos(y));
+    if cell == Cell::Wall {
+        rectangle([0.45, 0.45, 0.5, 1.0], [left, top, CELL_SIZE, CELL_SIZE], transform, gl);
+        rectangle([0.3, 0.3, 0.35, 1.0], [left + 4.0, top + 4.0, CELL_SIZE - 8.0, CELL_SIZE - 8.0],
+                  transform, gl);
+        return
+    }
+    rectangle(colour, [left, top, CELL_SIZE, CELL_SIZE], transform, gl);
+    match cell {
+        Cell::Upload => {
+            Rectangle::new_border([0.3, 0.7, 1.0, 0.9], 1.5)
+                .draw([left + 3.0, top + 3.0, CELL_SIZE - 6.0, CELL_SIZE - 6.0],
+                      default_draw_state(), transform, gl);
+        },
+        Cell::Data => {
+            rectangle([0.3, 1.0, 0.4, 0.9], [left + 9.0, top + 9.0, 10.0, 10.0], transform, gl);
+        },
+        Cell::Slow => {
+            for i in 0..3 {
+                rectangle([0.4, 0.5, 1.0, 0.7],
+                          [left + 4.0, top + 6.0 + 7.0*i as f64, CELL_SIZE - 8.0, 2.0],
+                          transform, gl);
+            }
+        },
+        Cell::Damage => {
+            rectangle([1.0, 0.45, 0.0, 0.6],
+                      [left + 6.0, top + 6.0, CELL_SIZE - 12.0, CELL_SIZE - 12.0], transform, gl);
+        },
+        Cell::Empty | Cell::Floor | Cell::Wall => {},
+    }
 }
 
 #[derive(Clone)]
@@ -39,8 +128,23 @@ impl Grid {
         }
     }
 
-    // XXX is this the best place for this?
-    pub fn from_string(s: &str) -> (Grid, VecMap<Unit>) {
+    /// A grid of the given size with every tile set to `cell`.
+    pub fn filled(width: usize, height: usize, cell: Cell) -> Grid {
+        let len = width * height;
+        Grid {
+            grid: vec![cell; len],
+            width: width,
+            highlight: vec![0; len],
+            attack_hi: vec![0; len],
+            attack_loc: None,
+            player_pos: None,
+        }
+    }
+
+    /// Parses the grid part of a level. `unit` is called for every tile that isn't one of the
+    /// cells' glyphs to create the unit starting there.
+    pub fn from_string<F>(s: &str, mut unit: F) -> (Grid, VecMap<Unit>)
+            where F: FnMut(char, (i16, i16)) -> Unit {
         let mut v = vec![];
         let mut units = VecMap::new();
         let mut width = None;
@@ -49,13 +153,12 @@ impl Grid {
         for line in s.lines() {
             let mut x = 0;
             for c in line.chars() {
-                match c {
-                    ' ' => v.push(Cell::Empty),
-                    '#' => v.push(Cell::Floor),
+                match Cell::from_glyph(c) {
+                    Some(cell) => v.push(cell),
                     // tile, player starting point, or enemy tile has floor underneath
-                    c => {
+                    None => {
                         v.push(Cell::Floor);
-                        units.insert(idx, Unit::from_char(c, (x, y)));
+                        units.insert(idx, unit(c, (x, y)));
                         idx += 1;
                     },
                 }
@@ -102,29 +205,32 @@ impl Grid {
 
     pub fn is_valid(&self, x: i16, y: i16) -> bool {
         if !self.is_in_bounds(x, y) { return false }
-        self[(x, y)] != Cell::Empty
+        self[(x, y)].is_walkable()
+    }
+
+    /// Every tile of the given kind, row by row.
+    pub fn find(&self, cell: Cell) -> Vec<(i16, i16)> {
+        let width = self.width;
+        self.grid.iter().enumerate()
+            .filter(|&(_, &c)| c == cell)
+            .map(|(i, _)| ((i % width) as i16, (i / width) as i16))
+            .collect()
     }
 
     pub fn is_in_bounds(&self, x: i16, y: i16) -> bool {
         x >= 0 && y >= 0 && x < self.width as i16 && y < self.height() as i16
     }
 
-    pub fn draw(&mut self, _: &Game, c: &Context, gl: &mut GlGraphics) {
-        use graphics::*;
-
-        for (i, (v, &hi)) in self.grid.iter_mut().zip(self.highlight.iter()).enumerate() {
+    pub fn draw(&mut self, game: &Game, c: &Context, gl: &mut GlGraphics) {
+        for (i, (&v, &hi)) in self.grid.iter().zip(self.highlight.iter()).enumerate() {
             let (x, y) = (i % self.width, i / self.width);
-            match *v {
+            if !game.is_shown(i) || game.anims.is_rising((x as i16, y as i16)) { continue }
+            match v {
                 Cell::Empty if self.attack_hi[i] == 0 => {},
                 _ => {
                     let mut alpha = if hi != 0 { 0.6 } else { 0.3 };
                     let gb = if self.attack_hi[i] > 0 { alpha = 0.3; 0.0 } else { 1.0 };
-                    rectangle([1.0, gb, gb, alpha],
-                              [cell_pos(x as i16),
-                               cell_pos(y as i16),
-                               CELL_SIZE, CELL_SIZE],
-                              c.transform,
-                              gl);
+                    draw_tile(v, (x as i16, y as i16), [1.0, gb, gb, alpha], c.transform, gl);
                 }
             }
         }
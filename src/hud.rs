@@ -0,0 +1,109 @@
+//! The side panel showing the current team, the selected unit and the End Turn button.
+
+use graphics::{Context, Rectangle, rectangle, default_draw_state};
+use opengl_graphics::GlGraphics;
+
+use super::Game;
+use text::{Text, Align};
+
+pub const PANEL_WIDTH: f64 = 130.0;
+const MARGIN: f64 = 10.0;
+const PIP_SIZE: f64 = 8.0;
+const PIP_GAP: f64 = 2.0;
+
+fn panel_x(size: [f64; 2]) -> f64 {
+    size[0] - PANEL_WIDTH
+}
+
+/// Where the End Turn button is in a window of the given size.
+pub fn end_turn_button(size: [f64; 2]) -> [f64; 4] {
+    [panel_x(size) + MARGIN, size[1] - MARGIN - 30.0, PANEL_WIDTH - 2.0*MARGIN, 30.0]
+}
+
+pub fn is_inside(rect: [f64; 4], (x, y): (f64, f64)) -> bool {
+    x >= rect[0] && y >= rect[1] && x < rect[0] + rect[2] && y < rect[1] + rect[3]
+}
+
+/// Draws a row of `total` pips starting at `(x, y)`, the first `filled` of which are solid.
+fn pips(colour: [f32; 3], filled: usize, total: usize, x: f64, y: f64,
+        c: &Context, gl: &mut GlGraphics) {
+    for i in 0..total {
+        let alpha = if i < filled { 1.0 } else { 0.25 };
+        rectangle([colour[0], colour[1], colour[2], alpha],
+                  [x + i as f64 * (PIP_SIZE + PIP_GAP), y, PIP_SIZE, PIP_SIZE],
+                  c.transform,
+                  gl);
+    }
+}
+
+pub fn draw(game: &Game, c: &Context, gl: &mut GlGraphics) {
+    let size = c.get_view_size();
+    let left = panel_x(size) + MARGIN;
+    let width = PANEL_WIDTH - 2.0*MARGIN;
+    let white = Text::new([1.0, 1.0, 1.0, 1.0]);
+    let grey = Text::new([0.6, 0.6, 0.6, 1.0]);
+    rectangle([0.08, 0.08, 0.08, 1.0], [panel_x(size), 0.0, PANEL_WIDTH, size[1]], c.transform, gl);
+
+    // Whose turn it is
+    let team = &game.teams[game.current_team as usize];
+    let colour = team.colour;
+    rectangle([colour[0], colour[1], colour[2], 1.0], [left, MARGIN, width, 16.0], c.transform, gl);
+    Text::new([0.0, 0.0, 0.0, 1.0]).align(Align::Centre)
+        .draw(&team.name, &game.font, left + width / 2.0, MARGIN + 4.5, c, gl);
+
+    // The selected unit
+    if let Some(unit) = game.selected_unit() {
+        let mut y = MARGIN + 26.0;
+        unit.draw_icon(game, [left, y, 40.0, 40.0], c, gl);
+        white.draw(unit.name, &game.font, left + 48.0, y + 4.0, c, gl);
+        y += 50.0;
+        grey.draw(&format!("Size {}/{}", unit.parts.len(), unit.len_limit), &game.font,
+                  left, y, c, gl);
+        y += 10.0;
+        pips([1.0, 1.0, 1.0], unit.parts.len(), unit.len_limit, left, y, c, gl);
+        y += PIP_SIZE + 6.0;
+        grey.draw(&format!("Moves {}/{}", unit.moves, unit.move_limit), &game.font,
+                  left, y, c, gl);
+        y += 10.0;
+        pips([0.3, 0.8, 1.0], unit.moves as usize, unit.move_limit as usize, left, y, c, gl);
+        y += PIP_SIZE + 12.0;
+
+        for (i, attack) in unit.attacks.iter().enumerate() {
+            let rect = [left, y, width, 28.0];
+            if unit.attack == Some(i as u16) {
+                rectangle([1.0, 1.0, 1.0, 0.2], rect, c.transform, gl);
+            }
+            let border = if unit.has_attacked { 0.3 } else { 0.8 };
+            Rectangle::new_border([1.0, 1.0, 1.0, border], 0.5)
+                .draw(rect, default_draw_state(), c.transform, gl);
+            white.draw(&format!("{} {}", i + 1, attack.name()), &game.font,
+                       left + 4.0, y + 4.0, c, gl);
+            let stats = if attack.damage() > 0 {
+                format!("Range {} Dmg {}", attack.range(), attack.damage())
+            } else {
+                format!("Range {}", attack.range())
+            };
+            grey.draw(&stats, &game.font, left + 4.0, y + 16.0, c, gl);
+            y += rect[3] + 6.0;
+        }
+    }
+
+    // The End Turn button, which can only be used by local players
+    let button = end_turn_button(size);
+    let round = match game.limit {
+        Some(limit) => format!("Round {}/{}", game.round, limit.rounds),
+        None => format!("Round {}", game.round),
+    };
+    grey.draw(&round, &game.font, left, button[1] - 28.0, c, gl);
+    grey.draw(&format!("Credits {}", team.credits), &game.font, left, button[1] - 16.0, c, gl);
+    let alpha = if !team.is_local() {
+        0.2
+    } else if is_inside(button, game.mouse) {
+        1.0
+    } else {
+        0.6
+    };
+    rectangle([colour[0], colour[1], colour[2], alpha], button, c.transform, gl);
+    Text::new([0.0, 0.0, 0.0, 1.0]).align(Align::Centre)
+        .draw("End Turn", &game.font, button[0] + button[2] / 2.0, button[1] + 11.5, c, gl);
+}
@@ -0,0 +1,25 @@
+//! Items lying on the grid for units to pick up.
+
+use graphics::math::Matrix2d;
+use opengl_graphics::GlGraphics;
+
+use super::{CELL_SIZE, cell_pos};
+
+/// A pile of credits. The first unit to move its head onto the item's tile picks it up and gives
+/// the credits to its team.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Item {
+    pub pos: (i16, i16),
+    pub credits: u32,
+}
+
+impl Item {
+    pub fn draw(&self, transform: Matrix2d, gl: &mut GlGraphics) {
+        use graphics::*;
+
+        let x = cell_pos(self.pos.0) + CELL_SIZE / 2.0;
+        let y = cell_pos(self.pos.1) + CELL_SIZE / 2.0;
+        ellipse([1.0, 0.8, 0.1, 1.0], [x - 7.0, y - 7.0, 14.0, 14.0], transform, gl);
+        ellipse([0.8, 0.55, 0.0, 1.0], [x - 4.0, y - 4.0, 8.0, 8.0], transform, gl);
+    }
+}
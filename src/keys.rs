@@ -0,0 +1,205 @@
+//! Key bindings for local players.
+//!
+//! The bindings are read from `keys.txt` when the game starts up. Each line of the file is a
+//! directive:
+//!
+//! - `layout <arrows|wasd|vim>` replaces every binding with one of the built-in layouts.
+//! - `<control> <key>...` binds the control to the given keys instead of the ones it had before.
+//!
+//! The controls are `up`, `down`, `left`, `right`, `next`, `attack1` to `attack9`, `fire`, `end`,
+//! `undo` and, for scrolling around big levels, `pan-up`, `pan-down`, `pan-left` and `pan-right`.
+//! Keys are named by the character on them (`a`, `1`, `/`) or, for keys without one, by `up`,
+//! `down`, `left`, `right`, `return`, `space`, `tab` or `backspace`.
+//!
+//! Blank lines and lines starting with `;` are ignored. Without a `keys.txt`, or if it has a
+//! mistake in it, the arrows layout is used. Escape, R and M always pause, restart and quit to the
+//! menu, whatever they are bound to.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use piston::input::Key;
+
+/// Something a local player can do with a key.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Control {
+    Up,
+    Down,
+    Left,
+    Right,
+    /// Selects the next of the team's units.
+    Next,
+    /// Enters or leaves attack mode with the given attack, counting from 0.
+    Attack(u16),
+    Fire,
+    EndTurn,
+    Undo,
+    /// Scrolls the view by the given number of tiles.
+    Pan(i16, i16),
+}
+
+impl Control {
+    pub fn from_name(name: &str) -> Option<Control> {
+        Some(match name {
+            "up" => Control::Up,
+            "down" => Control::Down,
+            "left" => Control::Left,
+            "right" => Control::Right,
+            "next" => Control::Next,
+            "fire" => Control::Fire,
+            "end" => Control::EndTurn,
+            "undo" => Control::Undo,
+            "pan-up" => Control::Pan(0, -1),
+            "pan-down" => Control::Pan(0, 1),
+            "pan-left" => Control::Pan(-1, 0),
+            "pan-right" => Control::Pan(1, 0),
+            _ if name.starts_with("attack") => {
+                match name[6..].parse::<u16>() {
+                    Ok(n) if n >= 1 && n <= 9 => Control::Attack(n - 1),
+                    _ => return None,
+                }
+            },
+            _ => return None,
+        })
+    }
+}
+
+pub fn key_from_name(name: &str) -> Option<Key> {
+    let key = match name {
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "return" => Key::Return,
+        "space" => Key::Space,
+        "tab" => Key::Tab,
+        "backspace" => Key::Backspace,
+        _ => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                // Keys with characters on them have the character's code, in lowercase
+                (Some(c), None) => Key::from(c.to_ascii_lowercase() as u32),
+                _ => return None,
+            }
+        },
+    };
+    if key == Key::Unknown { None } else { Some(key) }
+}
+
+#[derive(Clone, Debug)]
+pub struct Bindings {
+    keys: HashMap<Key, Control>,
+}
+
+/// The arrows layout.
+impl Default for Bindings {
+    fn default() -> Bindings {
+        Bindings::layout("arrows").unwrap()
+    }
+}
+
+impl Bindings {
+    /// Loads the bindings from `keys.txt`, or the default ones if there isn't one or it can't be
+    /// read.
+    pub fn load() -> Bindings {
+        if !Path::new("keys.txt").exists() { return Bindings::default() }
+        let result = Bindings::from_path("keys.txt")
+            .map_err(|e| e.to_string())
+            .and_then(|s| Bindings::from_string(&s));
+        match result {
+            Ok(bindings) => bindings,
+            Err(e) => {
+                eprintln!("couldn't load keys.txt, using the default keys instead: {}", e);
+                Bindings::default()
+            },
+        }
+    }
+
+    fn from_path<P: AsRef<Path>>(path: P) -> io::Result<String> {
+        let mut f = File::open(path)?;
+        let mut s = String::new();
+        f.read_to_string(&mut s)?;
+        Ok(s)
+    }
+
+    pub fn from_string(s: &str) -> Result<Bindings, String> {
+        let mut bindings = Bindings::default();
+        for line in s.lines().map(|l| l.trim()) {
+            if line.is_empty() || line.starts_with(";") { continue }
+            let words: Vec<_> = line.split_whitespace().collect();
+            if words[0] == "layout" {
+                if words.len() != 2 { return Err(format!("expected `layout <name>`: {}", line)) }
+                bindings = Bindings::layout(words[1])
+                    .ok_or_else(|| format!("unknown layout {:?}", words[1]))?;
+                continue
+            }
+            let control = Control::from_name(words[0])
+                .ok_or_else(|| format!("unknown control {:?}", words[0]))?;
+            bindings.keys.retain(|_, c| *c != control);
+            for name in &words[1..] {
+                let key = key_from_name(name).ok_or_else(|| format!("unknown key {:?}", name))?;
+                bindings.keys.insert(key, control);
+            }
+        }
+        Ok(bindings)
+    }
+
+    /// One of the built-in layouts, which differ only in the keys used for moving and panning.
+    pub fn layout(name: &str) -> Option<Bindings> {
+        let arrows = [Key::Up, Key::Down, Key::Left, Key::Right];
+        let (moves, pans) = match name {
+            "arrows" => (arrows, [Key::W, Key::S, Key::A, Key::D]),
+            "wasd" => ([Key::W, Key::S, Key::A, Key::D], arrows),
+            "vim" => ([Key::K, Key::J, Key::H, Key::L], arrows),
+            _ => return None,
+        };
+        let mut keys = HashMap::new();
+        for (&key, &control) in moves.iter().zip(&[Control::Up, Control::Down,
+                                                   Control::Left, Control::Right]) {
+            keys.insert(key, control);
+        }
+        for (&key, &control) in pans.iter().zip(&[Control::Pan(0, -1), Control::Pan(0, 1),
+                                                  Control::Pan(-1, 0), Control::Pan(1, 0)]) {
+            keys.insert(key, control);
+        }
+        let digits = [Key::D1, Key::D2, Key::D3, Key::D4, Key::D5,
+                      Key::D6, Key::D7, Key::D8, Key::D9];
+        for (i, &key) in digits.iter().enumerate() {
+            keys.insert(key, Control::Attack(i as u16));
+        }
+        keys.insert(Key::Tab, Control::Next);
+        keys.insert(Key::Return, Control::Fire);
+        keys.insert(Key::Space, Control::EndTurn);
+        keys.insert(Key::U, Control::Undo);
+        Some(Bindings {
+            keys: keys,
+        })
+    }
+
+    pub fn control(&self, key: Key) -> Option<Control> {
+        self.keys.get(&key).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use piston::input::Key;
+    use super::{Bindings, Control};
+
+    #[test]
+    fn rebinding_replaces_the_old_keys() {
+        let bindings = Bindings::from_string("layout vim\n; comment\nfire f space\n").unwrap();
+        assert_eq!(bindings.control(Key::H), Some(Control::Left));
+        assert_eq!(bindings.control(Key::F), Some(Control::Fire));
+        assert_eq!(bindings.control(Key::Space), Some(Control::Fire));
+        assert_eq!(bindings.control(Key::Return), None);
+    }
+
+    #[test]
+    fn mistakes_are_errors() {
+        for s in &["layout", "layout qwerty", "jump j", "fire shift", "attack10 0"] {
+            assert!(Bindings::from_string(s).is_err(), "{:?}", s);
+        }
+    }
+}
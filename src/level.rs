@@ -0,0 +1,418 @@
+//! Level files.
+//!
+//! A level file is an optional header followed by the grid itself. When there is a header, it is
+//! separated from the grid by a line containing only `---`. Each line of the header is a
+//! directive:
+//!
+//! - `team <name> <local|ai> [<r> <g> <b>]` adds a team. Teams are numbered from 0 in the order
+//!   they are declared.
+//! - `unit <glyph> <kind> <team>` makes `<glyph>` place a unit of the given kind (see
+//!   `Unit::from_kind`) on the given team.
+//! - `under <x> <y> <cell>` puts the given kind of cell under the unit starting at `(x, y)`,
+//!   instead of floor. Cells are named as in `cell` effects below.
+//! - `fog` turns on fog of war: teams can only see the tiles within sight of their units.
+//! - `credits <x> <y> <amount>` puts that many credits on the tile at `(x, y)`, counting from 0
+//!   at the top left, for the first unit to get there to pick up.
+//! - `survive <team> <rounds>` makes the given team win if it still has units once that many
+//!   rounds have been played, and `deadline <team> <rounds>` makes it lose if it hasn't won by
+//!   then. A level can have one or the other, but not both.
+//! - `on <trigger...> <effect...>` makes something happen the first time something else does
+//!   (see `script`). The triggers are:
+//!   - `round <n>`: round `n` starts, counting from 1.
+//!   - `turn <team>`: the team's turn starts.
+//!   - `destroyed <team>`: one of the team's units is destroyed.
+//!   - `enter <team> <x> <y>`: one of the team's units moves onto the tile at `(x, y)`.
+//!
+//!   And the effects are:
+//!   - `message <text...>`: shows the text to the players.
+//!   - `cell <x> <y> <cell>`: changes the tile at `(x, y)` to the given kind of cell, named as in
+//!     the editor but in lowercase with hyphens for spaces, such as `wall` or `upload-zone`.
+//!   - `spawn <kind> <team> <x> <y>`: brings in a new unit on the given team at `(x, y)`, or
+//!     the nearest free tile to it if there is a unit in the way.
+//!   - `win <team>`: ends the game, with the given team the winner.
+//!
+//! Blank lines and lines starting with `;` are ignored. Levels without any `team` directives get
+//! the usual player and AI teams, and glyphs without a `unit` directive fall back to
+//! `Unit::from_char`.
+//!
+//! In the grid, each character is one tile. A space is empty, `#` is floor, `%` is a wall, `@` an
+//! upload zone, `$` data, `~` a slowing tile and `!` a damaging tile (see `grid::Cell`). Any
+//! other character is a unit starting out on floor, unless an `under` directive says otherwise.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use vec_map::VecMap;
+
+use super::{Unit, Grid};
+use item::Item;
+use grid::Cell;
+use controller::ControllerKind;
+use script::{self, Trigger, Effect};
+
+/// Colours given to teams that don't specify their own.
+pub const TEAM_COLOURS: [[f32; 3]; 6] = [
+    [0.0, 0.7490196078431373, 0.9686274509803922],
+    [0.9725490196078431, 0.0, 0.06666666666666667],
+    [0.5647058823529412, 0.9882352941176471, 0.0],
+    [1.0, 0.8, 0.0],
+    [0.8, 0.3, 1.0],
+    [1.0, 0.5, 0.0],
+];
+
+#[derive(Clone)]
+pub struct TeamSpec {
+    pub name: String,
+    pub controller: ControllerKind,
+    pub colour: [f32; 3],
+}
+
+/// What a team has to do before the round limit runs out.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Goal {
+    /// Still have units left, which wins the game.
+    Survive,
+    /// Win the game, or lose it.
+    Win,
+}
+
+/// A limit on how many rounds a level lasts. A round is over once every team has had a turn.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TurnLimit {
+    pub team: u16,
+    pub rounds: u32,
+    pub goal: Goal,
+}
+
+#[derive(Clone)]
+pub struct Level {
+    pub grid: Grid,
+    pub units: VecMap<Unit>,
+    pub items: Vec<Item>,
+    pub teams: Vec<TeamSpec>,
+    pub fog: bool,
+    pub limit: Option<TurnLimit>,
+    /// The level's script: what happens when.
+    pub triggers: Vec<(Trigger, Effect)>,
+}
+
+/// The teams of levels that don't declare any.
+fn default_teams() -> Vec<TeamSpec> {
+    vec![
+        TeamSpec {
+            name: "Player".into(),
+            controller: ControllerKind::Local,
+            colour: TEAM_COLOURS[0],
+        },
+        TeamSpec {
+            name: "Enemy".into(),
+            controller: ControllerKind::Ai,
+            colour: TEAM_COLOURS[1],
+        },
+    ]
+}
+
+/// The glyphs given to each kind of unit on each team when a level is saved.
+const GLYPHS: &'static str = "123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+impl Level {
+    /// An empty level of the given size, with the default teams.
+    pub fn new(width: usize, height: usize) -> Level {
+        Level {
+            grid: Grid::filled(width, height, Cell::Floor),
+            units: VecMap::new(),
+            items: vec![],
+            teams: default_teams(),
+            fog: false,
+            limit: None,
+            triggers: vec![],
+        }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Level {
+        let mut f = File::open(path).unwrap();
+        let mut s = String::new();
+        f.read_to_string(&mut s).unwrap();
+        Level::from_string(&s)
+    }
+
+    pub fn from_string(s: &str) -> Level {
+        let lines: Vec<_> = s.lines().collect();
+        let (header, body) = match lines.iter().position(|l| l.trim() == "---") {
+            Some(i) => (&lines[..i], &lines[i + 1..]),
+            None => (&[][..], &lines[..]),
+        };
+
+        let mut teams = vec![];
+        let mut glyphs = HashMap::new();
+        let mut items = vec![];
+        let mut under = vec![];
+        let mut fog = false;
+        let mut limit = None;
+        let mut triggers = vec![];
+        for line in header {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(";") { continue }
+            let words: Vec<_> = line.split_whitespace().collect();
+            match words[0] {
+                "team" => {
+                    assert!(words.len() == 3 || words.len() == 6,
+                            "expected `team <name> <controller> [<r> <g> <b>]`: {}", line);
+                    let controller = ControllerKind::from_name(words[2])
+                        .expect("controller must be `local` or `ai`");
+                    let colour = if words.len() == 6 {
+                        [words[3].parse().unwrap(),
+                         words[4].parse().unwrap(),
+                         words[5].parse().unwrap()]
+                    } else {
+                        TEAM_COLOURS[teams.len() % TEAM_COLOURS.len()]
+                    };
+                    teams.push(TeamSpec {
+                        name: words[1].into(),
+                        controller: controller,
+                        colour: colour,
+                    });
+                },
+                "unit" => {
+                    assert!(words.len() == 4, "expected `unit <glyph> <kind> <team>`: {}", line);
+                    let glyph = words[1].chars().next().unwrap();
+                    assert!(Cell::from_glyph(glyph).is_none(),
+                            "{:?} is already used for a kind of cell", glyph);
+                    assert!(Unit::from_kind(words[2], (0, 0)).is_some(),
+                            "unknown unit kind: {}", words[2]);
+                    let team: u16 = words[3].parse().unwrap();
+                    glyphs.insert(glyph, (words[2], team));
+                },
+                "credits" => {
+                    assert!(words.len() == 4, "expected `credits <x> <y> <amount>`: {}", line);
+                    items.push(Item {
+                        pos: (words[1].parse().unwrap(), words[2].parse().unwrap()),
+                        credits: words[3].parse().unwrap(),
+                    });
+                },
+                "under" => {
+                    assert!(words.len() == 4, "expected `under <x> <y> <cell>`: {}", line);
+                    let pos: (i16, i16) = (words[1].parse().unwrap(), words[2].parse().unwrap());
+                    under.push((pos, script::parse_cell(words[3])));
+                },
+                "fog" => fog = true,
+                "survive" | "deadline" => {
+                    assert!(words.len() == 3, "expected `{} <team> <rounds>`: {}", words[0], line);
+                    assert!(limit.is_none(), "a level can only have one turn limit: {}", line);
+                    limit = Some(TurnLimit {
+                        team: words[1].parse().unwrap(),
+                        rounds: words[2].parse().unwrap(),
+                        goal: if words[0] == "survive" { Goal::Survive } else { Goal::Win },
+                    });
+                },
+                "on" => {
+                    assert!(words.len() >= 3, "expected `on <trigger...> <effect...>`: {}", line);
+                    let (trigger, len) = Trigger::from_words(&words[1..]);
+                    triggers.push((trigger, Effect::from_words(&words[1 + len..])));
+                },
+                d => panic!("unknown level directive: {}", d),
+            }
+        }
+
+        if teams.is_empty() {
+            teams = default_teams();
+        }
+
+        let body = body.join("\n");
+        let (mut grid, units) = Grid::from_string(&body, |c, coords| {
+            match glyphs.get(&c) {
+                Some(&(kind, team)) => {
+                    let mut unit = Unit::from_kind(kind, coords).unwrap();
+                    unit.team = team;
+                    unit
+                },
+                None => Unit::from_char(c, coords),
+            }
+        });
+        for unit in units.values() {
+            assert!((unit.team as usize) < teams.len(), "unit belongs to undeclared team {}",
+                    unit.team);
+        }
+        if let Some(limit) = limit {
+            assert!((limit.team as usize) < teams.len(), "turn limit for undeclared team {}",
+                    limit.team);
+        }
+        for &(trigger, ref effect) in &triggers {
+            for team in trigger.team().into_iter().chain(effect.team()) {
+                assert!((team as usize) < teams.len(),
+                        "script mentions undeclared team {}: on {} {}", team, trigger, effect);
+            }
+        }
+        for &(pos, cell) in &under {
+            assert!(units.values().any(|unit| unit.parts[0] == pos), "no unit starts at {:?}",
+                    pos);
+            assert!(cell.is_walkable(), "units can't stand on {}", script::cell_name(cell));
+            grid[pos] = cell;
+        }
+        for item in &items {
+            assert!(grid.is_valid(item.pos.0, item.pos.1), "credits at {:?} are out of reach",
+                    item.pos);
+        }
+
+        Level {
+            grid: grid,
+            units: units,
+            items: items,
+            teams: teams,
+            fog: fog,
+            limit: limit,
+            triggers: triggers,
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut f = File::create(path)?;
+        write!(f, "{}", self)
+    }
+}
+
+/// Writes the level out in the level file format.
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for team in &self.teams {
+            let controller = match team.controller {
+                ControllerKind::Local => "local",
+                ControllerKind::Ai => "ai",
+            };
+            writeln!(f, "team {} {} {} {} {}", team.name, controller,
+                     team.colour[0], team.colour[1], team.colour[2])?;
+        }
+        // Every kind of unit on every team gets its own glyph
+        let mut glyphs = HashMap::new();
+        let mut units = HashMap::new();
+        for unit in self.units.values() {
+            let key = (unit.kind, unit.team);
+            if !glyphs.contains_key(&key) {
+                let glyph = GLYPHS.chars().nth(glyphs.len()).expect("too many kinds of unit");
+                writeln!(f, "unit {} {} {}", glyph, unit.kind, unit.team)?;
+                glyphs.insert(key, glyph);
+            }
+            // Units always start out one tile long
+            let pos = unit.parts[0];
+            units.insert(pos, glyphs[&key]);
+            if self.grid[pos] != Cell::Floor {
+                writeln!(f, "under {} {} {}", pos.0, pos.1, script::cell_name(self.grid[pos]))?;
+            }
+        }
+        for item in &self.items {
+            writeln!(f, "credits {} {} {}", item.pos.0, item.pos.1, item.credits)?;
+        }
+        if self.fog {
+            writeln!(f, "fog")?;
+        }
+        if let Some(limit) = self.limit {
+            let directive = match limit.goal {
+                Goal::Survive => "survive",
+                Goal::Win => "deadline",
+            };
+            writeln!(f, "{} {} {}", directive, limit.team, limit.rounds)?;
+        }
+        for &(trigger, ref effect) in &self.triggers {
+            writeln!(f, "on {} {}", trigger, effect)?;
+        }
+        writeln!(f, "---")?;
+        for y in 0..self.grid.height() as i16 {
+            for x in 0..self.grid.width as i16 {
+                write!(f, "{}", match units.get(&(x, y)) {
+                    Some(&glyph) => glyph,
+                    None => self.grid[(x, y)].glyph(),
+                })?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use grid::Cell;
+    use super::{Level, Goal};
+
+    const LEVEL: &'static str = "\
+team Blue local 0 0 1
+team Red ai
+unit h hack 0
+unit w warden 1
+under 3 1 slow
+credits 1 1 25
+fog
+deadline 0 10
+on enter 0 4 1 message Made it
+on round 3 spawn sentinel 1 0 0
+---
+#%$@#
+#!~hw
+";
+
+    #[test]
+    fn saved_levels_load_the_same() {
+        let level = Level::from_string(LEVEL);
+        let saved = level.to_string();
+        let loaded = Level::from_string(&saved);
+        assert_eq!(loaded.to_string(), saved);
+
+        assert_eq!(loaded.teams.len(), 2);
+        assert_eq!(loaded.teams[0].name, "Blue");
+        assert_eq!(loaded.teams[1].colour, level.teams[1].colour);
+        let mut units: Vec<_> = loaded.units.values()
+            .map(|unit| (unit.kind, unit.team, unit.parts[0])).collect();
+        units.sort();
+        assert_eq!(units, [("hack", 0, (3, 1)), ("warden", 1, (4, 1))]);
+        assert_eq!(loaded.grid[(3, 1)], Cell::Slow);
+        assert_eq!(loaded.grid[(4, 1)], Cell::Floor);
+        assert_eq!(loaded.grid[(2, 1)], Cell::Slow);
+        assert_eq!(loaded.grid[(1, 0)], Cell::Wall);
+        assert_eq!(loaded.items[0].pos, (1, 1));
+        assert_eq!(loaded.items[0].credits, 25);
+        assert!(loaded.fog);
+        assert_eq!(loaded.limit.unwrap().goal, Goal::Win);
+        assert_eq!(loaded.triggers, level.triggers);
+    }
+
+    #[test]
+    fn levels_without_a_header_get_the_default_teams() {
+        let level = Level::from_string("#A\n1#\n");
+        assert_eq!(level.teams.len(), 2);
+        assert_eq!(level.units.len(), 2);
+        assert!(!level.fog);
+        assert!(level.limit.is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "a level can only have one turn limit")]
+    fn levels_have_at_most_one_turn_limit() {
+        Level::from_string("survive 0 5\ndeadline 1 5\n---\n#\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "unit belongs to undeclared team 2")]
+    fn units_need_declared_teams() {
+        Level::from_string("unit h hack 2\n---\nh#\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "script mentions undeclared team 5")]
+    fn scripts_need_declared_teams() {
+        Level::from_string("on round 2 win 5\n---\n#\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "no unit starts at (0, 0)")]
+    fn under_needs_a_unit() {
+        Level::from_string("under 0 0 slow\n---\n#\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown level directive: weather")]
+    fn unknown_directives_are_errors() {
+        Level::from_string("weather rain\n---\n#\n");
+    }
+}
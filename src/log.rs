@@ -0,0 +1,235 @@
+//! The combat log: a line describing each thing that happens in a game, shown in the corner of
+//! the grid and written to `combat.log` for debugging.
+//!
+//! With fog of war, the lines on screen only mention what the viewing team can see: things that
+//! happen out of its sight are left out, and units attacking from out of its sight aren't named.
+//! `combat.log` still gets everything.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::Write;
+
+use graphics::{Context, rectangle};
+use opengl_graphics::GlGraphics;
+
+use super::Game;
+use event::{Event, Subscription};
+use text::Text;
+use unit::UnitId;
+
+/// Where the log is written.
+const PATH: &'static str = "combat.log";
+/// How many of the latest lines are shown.
+const LINES: usize = 6;
+const LINE_HEIGHT: f64 = 10.0;
+const WIDTH: f64 = 320.0;
+const MARGIN: f64 = 10.0;
+const PADDING: f64 = 4.0;
+
+pub struct CombatLog {
+    subscription: Subscription,
+    /// The name, team and last known position of the head of every unit seen so far, so that
+    /// units can still be named once they have been destroyed.
+    units: BTreeMap<UnitId, (&'static str, u16, (i16, i16))>,
+    /// The latest lines, oldest first, with the team each one is about, if any.
+    lines: VecDeque<(String, Option<u16>)>,
+    /// The unit the last line says moved, so that a unit moving several tiles only takes up one
+    /// line on screen. The file still gets every step.
+    moving: Option<UnitId>,
+    file: Option<File>,
+}
+
+impl CombatLog {
+    /// Starts a new log, replacing whatever the last game wrote to `combat.log`.
+    pub fn new() -> CombatLog {
+        let file = match File::create(PATH) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                eprintln!("couldn't create {}: {}", PATH, e);
+                None
+            },
+        };
+        CombatLog {
+            file: file,
+            .. CombatLog::dummy()
+        }
+    }
+
+    /// A log that isn't written anywhere, for standing in while the real one is in use.
+    pub fn dummy() -> CombatLog {
+        CombatLog {
+            subscription: Subscription::new(),
+            units: BTreeMap::new(),
+            lines: VecDeque::new(),
+            moving: None,
+            file: None,
+        }
+    }
+
+    /// Adds lines for everything that has happened since the last update.
+    pub fn update(&mut self, game: &Game) {
+        for (&id, unit) in &game.units {
+            if let Some(&head) = unit.parts.front() {
+                self.units.insert(id, (unit.name, unit.team, head));
+            }
+        }
+        for event in self.subscription.poll(game) {
+            if let Event::UnitMoved { unit, to, .. } = *event {
+                if let Some(known) = self.units.get_mut(&unit) {
+                    known.2 = to;
+                }
+            }
+            if let Some((line, _)) = self.describe(event, game, None) {
+                if let Some(ref mut file) = self.file {
+                    if let Err(e) = writeln!(file, "{}", line) {
+                        eprintln!("couldn't write to {}: {}", PATH, e);
+                    }
+                }
+            }
+
+            let viewer = game.viewer;
+            let shown = if self.is_shown(event, viewer, game) {
+                self.describe(event, game, Some(viewer))
+            } else {
+                None
+            };
+            let (line, team) = match shown {
+                Some(line) => line,
+                None => {
+                    // The next move shown starts a new line, even if it's by the same unit
+                    if let Event::UnitMoved { .. } = *event {
+                        self.moving = None;
+                    }
+                    continue
+                },
+            };
+
+            let moving = match *event {
+                Event::UnitMoved { unit, .. } => Some(unit),
+                _ => None,
+            };
+            if moving.is_some() && moving == self.moving {
+                self.lines.pop_back();
+            }
+            self.moving = moving;
+            self.lines.push_back((line, team));
+            if self.lines.len() > LINES {
+                self.lines.pop_front();
+            }
+        }
+    }
+
+    /// Whether `viewer` can see the unit, or where it was last seen. Teams can always see their
+    /// own units, and without a viewer everything can be seen.
+    fn is_seen(&self, id: UnitId, viewer: Option<u16>, game: &Game) -> bool {
+        match (viewer, self.units.get(&id)) {
+            (Some(viewer), Some(&(_, team, (x, y)))) => {
+                team == viewer || game.can_see(viewer, x, y)
+            },
+            _ => true,
+        }
+    }
+
+    /// Whether `event` happened where `viewer` can see it.
+    fn is_shown(&self, event: &Event, viewer: u16, game: &Game) -> bool {
+        match *event {
+            Event::UnitMoved { to, .. } => game.can_see(viewer, to.0, to.1),
+            Event::UnitDamaged { unit, .. } | Event::UnitDestroyed { unit, .. }
+            | Event::UnitSpawned { unit, .. } => self.is_seen(unit, Some(viewer), game),
+            Event::TileChanged { pos, .. } => game.can_see(viewer, pos.0, pos.1),
+            _ => true,
+        }
+    }
+
+    fn name(&self, id: UnitId, viewer: Option<u16>, game: &Game) -> &'static str {
+        if !self.is_seen(id, viewer, game) { return "Something" }
+        self.units.get(&id).map_or("Unit", |&(name, _, _)| name)
+    }
+
+    fn team(&self, id: UnitId, viewer: Option<u16>, game: &Game) -> Option<u16> {
+        if !self.is_seen(id, viewer, game) { return None }
+        self.units.get(&id).map(|&(_, team, _)| team)
+    }
+
+    /// A line describing `event` and the team it is about, if it is worth mentioning. Units
+    /// `viewer` can't see aren't named.
+    fn describe(&self, event: &Event, game: &Game, viewer: Option<u16>)
+            -> Option<(String, Option<u16>)> {
+        let name_of = |id| self.name(id, viewer, game);
+        let team_of = |id| self.team(id, viewer, game);
+        Some(match *event {
+            Event::UnitMoved { unit, team, to, .. } => {
+                (format!("{} moved to {},{}", name_of(unit), to.0, to.1), Some(team))
+            },
+            Event::UnitDamaged { unit, cause: Some((by, attack)), amount } => {
+                let target = if by == unit { "itself" } else { name_of(unit) };
+                (format!("{} used {} on {}: -{}", name_of(by), attack, target, cells(amount)),
+                 team_of(by))
+            },
+            Event::UnitDamaged { unit, cause: None, amount } => {
+                (format!("{} hit a damaging tile: -{}", name_of(unit), cells(amount)),
+                 team_of(unit))
+            },
+            Event::UnitDestroyed { unit, team } => {
+                (format!("{} was destroyed", name_of(unit)), Some(team))
+            },
+            Event::UnitSpawned { unit, team } => {
+                (format!("{} arrived for {}", name_of(unit), game.teams[team as usize].name),
+                 Some(team))
+            },
+            Event::TileChanged { pos, from, to, cause: Some((by, attack)) } => {
+                (format!("{} used {} on {},{}: {} became {}", name_of(by), attack, pos.0, pos.1,
+                         from.name(), to.name()),
+                 team_of(by))
+            },
+            Event::TileChanged { pos, from, to, cause: None } => {
+                (format!("{},{}: {} became {}", pos.0, pos.1, from.name(), to.name()), None)
+            },
+            Event::RoundStarted { .. } => return None,
+            Event::TurnStarted { team, round } => {
+                (format!("Round {}: {}'s turn", round, game.teams[team as usize].name),
+                 Some(team))
+            },
+            Event::Undone => ("Undone".into(), None),
+            Event::GameOver { winner } => {
+                (format!("{} won", game.teams[winner as usize].name), Some(winner))
+            },
+        })
+    }
+
+    /// Draws the latest lines in the bottom left corner of the grid, each in the colour of the
+    /// team it is about.
+    pub fn draw(&self, game: &Game, c: &Context, gl: &mut GlGraphics) {
+        if self.lines.is_empty() { return }
+        let size = c.get_view_size();
+        let height = self.lines.len() as f64 * LINE_HEIGHT + 2.0*PADDING;
+        let top = size[1] - MARGIN - height;
+        rectangle([0.0, 0.0, 0.0, 0.6], [MARGIN, top, WIDTH, height], c.transform, gl);
+
+        for (i, &(ref line, team)) in self.lines.iter().enumerate() {
+            let colour = match team {
+                Some(team) => {
+                    let colour = game.teams[team as usize].colour;
+                    [colour[0], colour[1], colour[2], 1.0]
+                },
+                None => [0.8, 0.8, 0.8, 1.0],
+            };
+            // Lines too long for the box lose their end
+            let mut line = line.clone();
+            while game.font.width(&line, 1.0) > WIDTH - 2.0*PADDING {
+                line.pop();
+            }
+            Text::new(colour).draw(&line, &game.font, MARGIN + PADDING,
+                                   top + PADDING + i as f64 * LINE_HEIGHT + 1.0, c, gl);
+        }
+    }
+}
+
+/// How many cells `amount` is, in words.
+fn cells(amount: usize) -> String {
+    if amount == 1 {
+        "1 cell".into()
+    } else {
+        format!("{} cells", amount)
+    }
+}
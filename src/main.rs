@@ -11,8 +11,11 @@ extern crate graphics;
 extern crate opengl_graphics;
 extern crate vec_map;
 
+use std::env;
+use std::fs::File;
+use std::io::Read;
 use piston::input::*;
-use piston::window::WindowSettings;
+use piston::window::{Window, WindowSettings};
 use opengl_graphics::*;
 use piston_window::PistonWindow;
 
@@ -28,16 +31,77 @@ pub use grid::Grid;
 pub mod controller;
 pub use controller::Controller;
 
+pub mod level;
+pub use level::Level;
+
+pub mod net;
+
+pub mod anim;
+
+pub mod hud;
+
+pub mod text;
+
+pub mod keys;
+
+pub mod pad;
+
+pub mod screen;
+
+pub mod editor;
+use screen::App;
+
+pub mod camera;
+
+pub mod item;
+
+pub mod deploy;
+
+pub mod campaign;
+
+pub mod profile;
+
+pub mod script;
+
+pub mod event;
+
+pub mod log;
+
 pub const CELL_SIZE: f64 = 28.0;
 pub const CELL_PADDING: f64 = 4.0;
-pub const CELL_OFFSET_X: f64 = 50.0;
-pub const CELL_OFFSET_Y: f64 = 50.0;
 
+/// Where the tile in the given row or column starts, in the grid's coordinates.
 pub fn cell_pos(a: i16) -> f64 {
-    CELL_OFFSET_X + a as f64 * (CELL_SIZE + CELL_PADDING)
+    a as f64 * (CELL_SIZE + CELL_PADDING)
 }
 
 fn main() {
+    // Usage: slydot [LEVEL [CONTROLLER...]]
+    //        slydot --host [ADDRESS:]PORT [LEVEL]
+    //        slydot --join HOST:PORT
+    // Without a level, the game starts at the title menu. Each CONTROLLER (`local` or `ai`)
+    // overrides the controller of the corresponding team.
+    let mut args = env::args().skip(1).peekable();
+    let session = match args.peek().map(|a| &a[..]) {
+        Some("--host") => {
+            args.next();
+            let addr = args.next().expect("--host needs a port");
+            // Only players on this machine can join unless an address to listen on is given,
+            // such as 0.0.0.0:PORT for every network the machine is on
+            let addr = if addr.contains(':') { addr } else { format!("127.0.0.1:{}", addr) };
+            let path = args.next().unwrap_or("levels/test.sunrise".into());
+            let mut level = String::new();
+            File::open(&path).unwrap().read_to_string(&mut level).unwrap();
+            Some(net::host(&addr[..], level).unwrap())
+        },
+        Some("--join") => {
+            args.next();
+            let addr = args.next().expect("--join needs an address");
+            Some(net::join(&addr[..]).unwrap())
+        },
+        _ => None,
+    };
+
     let opengl = OpenGL::V3_2;
     let window: PistonWindow =
         WindowSettings::new("sunrise", [640, 480])
@@ -46,22 +110,54 @@ fn main() {
         .unwrap();
 
     let ref mut gl = GlGraphics::new(opengl);
-    let mut game = Game::sample();
-    let idx = game.units.iter().find(|&(_, ref x)| x.team == 0).unwrap().0;
-    game.select(idx);
-    for e in window {
+    let mut app = match session {
+        Some(session) => App::playing_online(session.start()),
+        None => match args.next() {
+            Some(path) => {
+                let mut level = Level::from_path(&path);
+                for (team, arg) in level.teams.iter_mut().zip(args) {
+                    team.controller = controller::ControllerKind::from_name(&arg)
+                        .expect("controller must be `local` or `ai`");
+                }
+                App::playing(level)
+            },
+            None => App::new(),
+        },
+    };
+    let mut joystick = pad::Joystick::open();
+    for mut e in window {
         if let Some(args) = e.render_args() {
             gl.draw(args.viewport(), |c, gl| {
-                game.draw(&c, gl);
+                app.draw(&c, gl);
             });
-            game.handle_frame();
+            app.handle_frame();
         }
 
+        if let Some(a) = e.resize_args() {
+            app.handle_resize(a[0], a[1]);
+        }
         if let Some(a) = e.mouse_cursor_args() {
-            game.handle_mouse(a[0], a[1]);
+            app.handle_mouse(a[0], a[1]);
+        }
+        if let Some(a) = e.mouse_scroll_args() {
+            app.handle_scroll(a[1]);
         }
         if let Some(b) = e.press_args() {
-            game.handle_press(b);
+            app.handle_press(b);
+        }
+        if let Some(b) = e.release_args() {
+            app.handle_release(b);
+        }
+        if let Some(inside) = e.cursor_args() {
+            app.handle_cursor(inside);
+        }
+        if let Some(ref mut joystick) = joystick {
+            for event in joystick.poll() {
+                app.handle_pad(event);
+            }
+        }
+        if app.quit {
+            e.set_should_close(true);
         }
     }
 }
@@ -0,0 +1,447 @@
+//! Playing over a network.
+//!
+//! One player hosts a level and the other joins it over TCP. The protocol is line-based. Both
+//! sides start by sending `slydot <version>`, and give up if the versions differ. The host then
+//! sends `level <length>` followed by the contents of the level file, and `team <n>` to tell the
+//! client which team it plays as.
+//!
+//! After that, every line is one message:
+//!
+//! - `select <unit>`, `next`, `move <dx> <dy>`, `attack <slot>`, `target <x> <y>`, `fire`, `wait`,
+//!   `end` and `undo` are the actions in `game::Action`.
+//! - `hash <state>` follows every `end`, with the `Game::state_hash` of the game at the start of
+//!   the next turn. The receiver compares it to its own to detect the games going out of sync.
+//!
+//! Each side sends the actions of the teams it controls (including its AI teams) and applies the
+//! actions it receives for the other teams, after checking they make sense in its own game. An
+//! action that doesn't stops the game, the same as a bad line or a mismatched hash.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::rc::Rc;
+use piston::input::Button;
+
+use super::{Game, Controller, Level};
+use game::Action;
+use unit::UnitId;
+use controller::ControllerKind;
+use keys::Control;
+
+/// Bump this whenever the protocol changes.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The team played by whoever joins a hosted game.
+const CLIENT_TEAM: u16 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Action(Action),
+    Hash(u64),
+}
+
+impl Message {
+    pub fn encode(&self) -> String {
+        match *self {
+            Message::Action(action) => match action {
+                Action::Select(id) => format!("select {}", id.0),
+                Action::SelectNext => "next".into(),
+                Action::Move(dx, dy) => format!("move {} {}", dx, dy),
+                Action::Attack(attack) => format!("attack {}", attack),
+                Action::Target(x, y) => format!("target {} {}", x, y),
+                Action::Fire => "fire".into(),
+                Action::Wait => "wait".into(),
+                Action::EndTurn => "end".into(),
+                Action::Undo => "undo".into(),
+            },
+            Message::Hash(hash) => format!("hash {}", hash),
+        }
+    }
+
+    pub fn decode(line: &str) -> Option<Message> {
+        let words: Vec<_> = line.split_whitespace().collect();
+        if words.is_empty() { return None }
+        if words[0] == "hash" {
+            if words.len() != 2 { return None }
+            return words[1].parse().ok().map(Message::Hash)
+        }
+        let args: Vec<i64> = match words[1..].iter().map(|w| w.parse()).collect() {
+            Ok(args) => args,
+            Err(_) => return None,
+        };
+        let action = match (words[0], args.len()) {
+            ("select", 1) => Action::Select(UnitId(args[0] as u32)),
+            ("next", 0) => Action::SelectNext,
+            ("move", 2) => Action::Move(args[0] as i16, args[1] as i16),
+            ("attack", 1) => Action::Attack(args[0] as u16),
+            ("target", 2) => Action::Target(args[0] as i16, args[1] as i16),
+            ("fire", 0) => Action::Fire,
+            ("wait", 0) => Action::Wait,
+            ("end", 0) => Action::EndTurn,
+            ("undo", 0) => Action::Undo,
+            _ => return None,
+        };
+        Some(Message::Action(action))
+    }
+}
+
+/// The connection to the other player, shared by all of the `NetworkController`s in a game.
+pub struct Connection {
+    stream: TcpStream,
+    buf: Vec<u8>,
+    /// How many entries of `Game::history` have been sent or were received from the other side.
+    seen: usize,
+    /// Our own state hashes at the start of each turn the other side has ended, waiting to be
+    /// compared to theirs.
+    expected: VecDeque<u64>,
+    /// Set when the connection is lost or the games go out of sync; nothing more is sent or
+    /// applied after that.
+    pub broken: bool,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> Connection {
+        Connection {
+            stream: stream,
+            buf: vec![],
+            seen: 0,
+            expected: VecDeque::new(),
+            broken: false,
+        }
+    }
+
+    fn send_line(&mut self, line: &str) -> io::Result<()> {
+        self.stream.write_all(line.as_bytes())?;
+        self.stream.write_all(b"\n")
+    }
+
+    pub fn send(&mut self, message: Message) {
+        if self.broken { return }
+        if let Err(e) = self.send_line(&message.encode()) {
+            self.fail(&format!("connection lost: {}", e));
+        }
+    }
+
+    /// Reads into the buffer, returning `false` if the connection has been closed.
+    fn fill(&mut self) -> io::Result<bool> {
+        let mut chunk = [0; 1024];
+        let n = self.stream.read(&mut chunk)?;
+        self.buf.extend(chunk[..n].iter().cloned());
+        Ok(n > 0)
+    }
+
+    fn take_line(&mut self) -> Option<String> {
+        self.buf.iter().position(|&b| b == b'\n').map(|i| {
+            let line: Vec<_> = self.buf.drain(..i + 1).collect();
+            String::from_utf8_lossy(&line[..i]).into_owned()
+        })
+    }
+
+    /// Blocks until a whole line has arrived. Only used during the handshake.
+    fn read_line(&mut self) -> io::Result<String> {
+        loop {
+            if let Some(line) = self.take_line() { return Ok(line) }
+            if !self.fill()? {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"))
+            }
+        }
+    }
+
+    fn read_bytes(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        while self.buf.len() < len {
+            if !self.fill()? {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"))
+            }
+        }
+        Ok(self.buf.drain(..len).collect())
+    }
+
+    fn handshake(&mut self) -> io::Result<()> {
+        self.send_line(&format!("slydot {}", PROTOCOL_VERSION))?;
+        let line = self.read_line()?;
+        if line != format!("slydot {}", PROTOCOL_VERSION) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("incompatible peer: {}", line)))
+        }
+        Ok(())
+    }
+
+    /// Returns every message that has fully arrived so far without blocking.
+    pub fn poll(&mut self) -> Vec<Message> {
+        let mut messages = vec![];
+        if self.broken { return messages }
+        loop {
+            match self.fill() {
+                Ok(true) => {},
+                Ok(false) => {
+                    self.fail("connection closed by the other player");
+                    break
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    self.fail(&format!("connection lost: {}", e));
+                    break
+                },
+            }
+        }
+        while let Some(line) = self.take_line() {
+            match Message::decode(&line) {
+                Some(message) => messages.push(message),
+                None => {
+                    self.fail(&format!("bad message: {}", line));
+                    break
+                },
+            }
+        }
+        messages
+    }
+
+    fn fail(&mut self, reason: &str) {
+        if !self.broken {
+            eprintln!("network game stopped: {}", reason);
+        }
+        self.broken = true;
+    }
+}
+
+/// Controls a team in a networked game. On the side where the team is played, it wraps the
+/// team's real controller and sends everything it does to the other side; on the other side, it
+/// applies what it receives.
+pub struct NetworkController {
+    conn: Rc<RefCell<Connection>>,
+    inner: Option<Box<Controller>>,
+}
+
+impl NetworkController {
+    pub fn sender(conn: Rc<RefCell<Connection>>, inner: Box<Controller>) -> NetworkController {
+        NetworkController {
+            conn: conn,
+            inner: Some(inner),
+        }
+    }
+
+    pub fn receiver(conn: Rc<RefCell<Connection>>) -> NetworkController {
+        NetworkController {
+            conn: conn,
+            inner: None,
+        }
+    }
+
+    /// Sends any actions performed since we last looked.
+    fn flush(&mut self, game: &mut Game) {
+        let mut conn = self.conn.borrow_mut();
+        while conn.seen < game.history.len() {
+            let action = game.history[conn.seen];
+            conn.seen += 1;
+            conn.send(Message::Action(action));
+            if action == Action::EndTurn {
+                conn.send(Message::Hash(game.state_hash()));
+            }
+        }
+    }
+
+    fn receive(&mut self, game: &mut Game) {
+        let messages = self.conn.borrow_mut().poll();
+        for message in messages {
+            let mut conn = self.conn.borrow_mut();
+            if conn.broken { return }
+            match message {
+                Message::Action(action) => {
+                    if self.inner.is_some() {
+                        conn.fail("the other player acted during our turn");
+                        return
+                    }
+                    if !game.can_perform(action) {
+                        conn.fail(&format!("the other player sent an impossible action: {}",
+                                           Message::Action(action).encode()));
+                        return
+                    }
+                    drop(conn);
+                    game.perform(action);
+                    let mut conn = self.conn.borrow_mut();
+                    conn.seen = game.history.len();
+                    if action == Action::EndTurn {
+                        conn.expected.push_back(game.state_hash());
+                    }
+                },
+                Message::Hash(theirs) => {
+                    match conn.expected.pop_front() {
+                        Some(ours) if ours == theirs => {},
+                        _ => conn.fail("the games are out of sync"),
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl Controller for NetworkController {
+    fn handle_press(&mut self, game: &mut Game, button: Button) {
+        if let Some(ref mut inner) = self.inner {
+            inner.handle_press(game, button);
+        }
+        self.flush(game);
+    }
+
+    fn handle_control(&mut self, game: &mut Game, control: Control) {
+        if let Some(ref mut inner) = self.inner {
+            inner.handle_control(game, control);
+        }
+        self.flush(game);
+    }
+
+    fn handle_mouse(&mut self, game: &mut Game) {
+        if let Some(ref mut inner) = self.inner {
+            inner.handle_mouse(game);
+        }
+        self.flush(game);
+    }
+
+    fn handle_frame(&mut self, game: &mut Game) {
+        // Hashes can arrive after the turn has already passed to us, so always check for them.
+        self.receive(game);
+        if let Some(ref mut inner) = self.inner {
+            inner.handle_frame(game);
+        }
+        self.flush(game);
+    }
+
+    fn is_local_controlled(&self) -> bool {
+        self.inner.as_ref().map_or(false, |inner| inner.is_local_controlled())
+    }
+}
+
+/// Gives every team in `game` a `NetworkController`; the teams for which `ours` returns true are
+/// played here.
+fn connect_teams<F>(game: &mut Game, conn: Connection, ours: F) where F: Fn(u16) -> bool {
+    let conn = Rc::new(RefCell::new(conn));
+    for (i, team) in game.teams.iter_mut().enumerate() {
+        let conn = conn.clone();
+        if ours(i as u16) {
+            team.wrap_controller(|inner| Box::new(NetworkController::sender(conn, inner)));
+        } else {
+            team.wrap_controller(|_| Box::new(NetworkController::receiver(conn)));
+        }
+    }
+}
+
+/// A connected network game that is ready to start.
+pub struct Session {
+    conn: Connection,
+    level: String,
+    /// The team played on this side.
+    team: u16,
+}
+
+impl Session {
+    /// Starts the game. This loads textures, so it has to be called after the window is created.
+    pub fn start(self) -> Game {
+        let Session { conn, level, team } = self;
+        let mut game = Game::new(Level::from_string(&level));
+        if team == CLIENT_TEAM {
+            // Whoever joins plays their team themselves, even if the level gives it to the AI
+            game.teams[team as usize].wrap_controller(|_| ControllerKind::Local.create());
+            connect_teams(&mut game, conn, |t| t == team);
+        } else {
+            connect_teams(&mut game, conn, |t| t != CLIENT_TEAM);
+        }
+        game
+    }
+}
+
+/// Waits for another player to connect to `addr` and sends them the level, which they play as
+/// team 1.
+pub fn host(addr: &str, level: String) -> io::Result<Session> {
+    assert!(Level::from_string(&level).teams.len() > CLIENT_TEAM as usize,
+            "network games need at least two teams");
+    let listener = TcpListener::bind(addr)?;
+    println!("waiting for a player to join on {}...", addr);
+    let (stream, addr) = listener.accept()?;
+    println!("{} joined", addr);
+    let mut conn = Connection::new(stream);
+    conn.handshake()?;
+    conn.send_line(&format!("level {}", level.len()))?;
+    conn.stream.write_all(level.as_bytes())?;
+    conn.send_line(&format!("team {}", CLIENT_TEAM))?;
+    conn.stream.set_nonblocking(true)?;
+    Ok(Session {
+        conn: conn,
+        level: level,
+        team: 0,
+    })
+}
+
+/// Joins a game hosted at `addr`.
+pub fn join<A: ToSocketAddrs>(addr: A) -> io::Result<Session> {
+    let stream = TcpStream::connect(addr)?;
+    let mut conn = Connection::new(stream);
+    conn.handshake()?;
+
+    let line = conn.read_line()?;
+    let len = match expect_header(&line, "level") {
+        Some(len) => len,
+        None => return Err(bad_header(&line)),
+    };
+    let level = conn.read_bytes(len as usize)?;
+    let level = match String::from_utf8(level) {
+        Ok(level) => level,
+        Err(_) => return Err(bad_header("level")),
+    };
+    let line = conn.read_line()?;
+    let team = match expect_header(&line, "team") {
+        Some(team) => team as u16,
+        None => return Err(bad_header(&line)),
+    };
+    conn.stream.set_nonblocking(true)?;
+    Ok(Session {
+        conn: conn,
+        level: level,
+        team: team,
+    })
+}
+
+/// Parses a handshake line of the form `<name> <number>`.
+fn expect_header(line: &str, name: &str) -> Option<u64> {
+    let words: Vec<_> = line.split_whitespace().collect();
+    if words.len() != 2 || words[0] != name { return None }
+    words[1].parse().ok()
+}
+
+fn bad_header(line: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("unexpected message: {}", line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Message;
+    use game::Action;
+    use unit::UnitId;
+
+    #[test]
+    fn messages_round_trip() {
+        let messages = [
+            Message::Action(Action::Select(UnitId(3))),
+            Message::Action(Action::SelectNext),
+            Message::Action(Action::Move(-1, 0)),
+            Message::Action(Action::Attack(1)),
+            Message::Action(Action::Target(4, -2)),
+            Message::Action(Action::Fire),
+            Message::Action(Action::Wait),
+            Message::Action(Action::EndTurn),
+            Message::Action(Action::Undo),
+            Message::Hash(18446744073709551615),
+        ];
+        for &message in &messages {
+            assert_eq!(Message::decode(&message.encode()), Some(message));
+        }
+    }
+
+    #[test]
+    fn bad_messages_are_rejected() {
+        for line in &["", "teleport 1 2", "move 1", "move 1 2 3", "select x", "fire 1",
+                      "hash", "hash -1"] {
+            assert_eq!(Message::decode(line), None, "{:?}", line);
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! Gamepad input.
+//!
+//! The version of piston we use doesn't know about gamepads, so on Linux they are read from the
+//! joystick device directly by `Joystick`. Either way, gamepad input arrives as `PadEvent`s,
+//! which `Pad` turns into the same `Control`s as the keyboard.
+//!
+//! The d-pad and left stick move, the right stick moves attack targets, A fires, B undoes, X and
+//! Y pick the first and second attacks, the right bumper selects the next unit, Back ends the
+//! turn and Start pauses.
+
+use std::fs::File;
+use std::io::Read;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use keys::Control;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PadButton {
+    A,
+    B,
+    X,
+    Y,
+    LeftBumper,
+    RightBumper,
+    Back,
+    Start,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stick {
+    Left,
+    Right,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PadEvent {
+    Press(PadButton),
+    /// A stick has moved to `(x, y)`. Both go from -1 to 1, and positive `y` is down.
+    Stick(Stick, f64, f64),
+}
+
+/// How far a stick has to be pushed to count as a move.
+const PUSH: f64 = 0.6;
+/// How far back towards the middle a stick has to go before it can make another move.
+const RELEASE: f64 = 0.3;
+
+pub fn button_control(button: PadButton) -> Option<Control> {
+    match button {
+        PadButton::A => Some(Control::Fire),
+        PadButton::B => Some(Control::Undo),
+        PadButton::X => Some(Control::Attack(0)),
+        PadButton::Y => Some(Control::Attack(1)),
+        PadButton::RightBumper => Some(Control::Next),
+        PadButton::Back => Some(Control::EndTurn),
+        PadButton::DPadUp => Some(Control::Up),
+        PadButton::DPadDown => Some(Control::Down),
+        PadButton::DPadLeft => Some(Control::Left),
+        PadButton::DPadRight => Some(Control::Right),
+        PadButton::LeftBumper | PadButton::Start => None,
+    }
+}
+
+/// Turns gamepad input into controls. Each push of a stick is one move, like a key press.
+pub struct Pad {
+    /// Whether each stick has been pushed and not yet let go.
+    pushed: [bool; 2],
+}
+
+impl Pad {
+    pub fn new() -> Pad {
+        Pad {
+            pushed: [false; 2],
+        }
+    }
+
+    pub fn control(&mut self, event: PadEvent) -> Option<Control> {
+        match event {
+            PadEvent::Press(button) => button_control(button),
+            PadEvent::Stick(stick, x, y) => {
+                let pushed = &mut self.pushed[stick as usize];
+                let distance = x.abs().max(y.abs());
+                if *pushed {
+                    if distance < RELEASE {
+                        *pushed = false;
+                    }
+                    return None
+                }
+                if distance < PUSH { return None }
+                *pushed = true;
+                Some(if x.abs() > y.abs() {
+                    if x > 0.0 { Control::Right } else { Control::Left }
+                } else {
+                    if y > 0.0 { Control::Down } else { Control::Up }
+                })
+            },
+        }
+    }
+}
+
+/// A gamepad read through the Linux joystick API, using the usual layout of Xbox-style pads.
+pub struct Joystick {
+    events: Receiver<[u8; 8]>,
+    /// Where each stick is, since the joystick reports each axis separately.
+    sticks: [(f64, f64); 2],
+}
+
+impl Joystick {
+    /// Opens the first joystick, if there is one.
+    pub fn open() -> Option<Joystick> {
+        let mut device = match File::open("/dev/input/js0") {
+            Ok(device) => device,
+            Err(_) => return None,
+        };
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut event = [0; 8];
+            while device.read_exact(&mut event).is_ok() {
+                if tx.send(event).is_err() { break }
+            }
+        });
+        Some(Joystick {
+            events: rx,
+            sticks: [(0.0, 0.0); 2],
+        })
+    }
+
+    /// Returns everything that has happened since the last call.
+    pub fn poll(&mut self) -> Vec<PadEvent> {
+        let mut events = vec![];
+        while let Ok(event) = self.events.try_recv() {
+            // Each event is a timestamp, a value, a type and the number of the button or axis
+            let value = (event[4] as u16 | (event[5] as u16) << 8) as i16;
+            let kind = event[6];
+            let number = event[7];
+            // Skip the events describing the initial state of the joystick
+            if kind & 0x80 != 0 { continue }
+            if kind == 1 && value == 1 {
+                let button = match number {
+                    0 => PadButton::A,
+                    1 => PadButton::B,
+                    2 => PadButton::X,
+                    3 => PadButton::Y,
+                    4 => PadButton::LeftBumper,
+                    5 => PadButton::RightBumper,
+                    6 => PadButton::Back,
+                    7 => PadButton::Start,
+                    _ => continue,
+                };
+                events.push(PadEvent::Press(button));
+            } else if kind == 2 {
+                let value = value as f64 / 32767.0;
+                let (stick, is_x) = match number {
+                    0 => (Stick::Left, true),
+                    1 => (Stick::Left, false),
+                    3 => (Stick::Right, true),
+                    4 => (Stick::Right, false),
+                    // The d-pad is a pair of axes that are only ever -1, 0 or 1
+                    6 | 7 => {
+                        let button = match (number, value > 0.0) {
+                            _ if value == 0.0 => continue,
+                            (6, false) => PadButton::DPadLeft,
+                            (6, true) => PadButton::DPadRight,
+                            (_, false) => PadButton::DPadUp,
+                            (_, true) => PadButton::DPadDown,
+                        };
+                        events.push(PadEvent::Press(button));
+                        continue
+                    },
+                    _ => continue,
+                };
+                let pos = &mut self.sticks[stick as usize];
+                if is_x { pos.0 = value } else { pos.1 = value }
+                events.push(PadEvent::Stick(stick, pos.0, pos.1));
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use keys::Control;
+    use super::{Pad, PadButton, PadEvent, Stick, button_control};
+
+    #[test]
+    fn buttons_map_to_controls() {
+        assert_eq!(button_control(PadButton::A), Some(Control::Fire));
+        assert_eq!(button_control(PadButton::B), Some(Control::Undo));
+        assert_eq!(button_control(PadButton::X), Some(Control::Attack(0)));
+        assert_eq!(button_control(PadButton::Y), Some(Control::Attack(1)));
+        assert_eq!(button_control(PadButton::RightBumper), Some(Control::Next));
+        assert_eq!(button_control(PadButton::Back), Some(Control::EndTurn));
+        assert_eq!(button_control(PadButton::DPadUp), Some(Control::Up));
+        assert_eq!(button_control(PadButton::DPadLeft), Some(Control::Left));
+        // Start pauses, which the game handles itself
+        assert_eq!(button_control(PadButton::Start), None);
+        assert_eq!(button_control(PadButton::LeftBumper), None);
+
+        let mut pad = Pad::new();
+        assert_eq!(pad.control(PadEvent::Press(PadButton::DPadDown)), Some(Control::Down));
+    }
+
+    #[test]
+    fn sticks_move_once_per_push() {
+        let mut pad = Pad::new();
+        // Not far enough to count
+        assert_eq!(pad.control(PadEvent::Stick(Stick::Left, 0.5, 0.0)), None);
+        assert_eq!(pad.control(PadEvent::Stick(Stick::Left, 0.7, 0.2)), Some(Control::Right));
+        // Held, or only partly let go
+        assert_eq!(pad.control(PadEvent::Stick(Stick::Left, 1.0, 0.0)), None);
+        assert_eq!(pad.control(PadEvent::Stick(Stick::Left, 0.4, 0.0)), None);
+        assert_eq!(pad.control(PadEvent::Stick(Stick::Left, 0.0, -0.9)), None);
+        // Let go, then pushed again
+        assert_eq!(pad.control(PadEvent::Stick(Stick::Left, 0.1, 0.1)), None);
+        assert_eq!(pad.control(PadEvent::Stick(Stick::Left, 0.0, -0.9)), Some(Control::Up));
+    }
+
+    #[test]
+    fn sticks_are_independent() {
+        let mut pad = Pad::new();
+        assert_eq!(pad.control(PadEvent::Stick(Stick::Left, -0.8, 0.0)), Some(Control::Left));
+        assert_eq!(pad.control(PadEvent::Stick(Stick::Right, 0.0, 0.8)), Some(Control::Down));
+    }
+}
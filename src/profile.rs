@@ -0,0 +1,298 @@
+//! The player's progress, saved between runs of the game.
+//!
+//! The profile is kept in `profile.txt`, which is rewritten whenever something changes. Each line
+//! is a directive:
+//!
+//! - `credits <amount>` is how many credits the player has to spend in the shop.
+//! - `unit <kind> [size <n>] [speed <n>] [attacks <attack>,...]` adds a unit of the given kind
+//!   (see `Unit::from_kind`) to the player's roster, the units they can upload at the start of a
+//!   campaign level. `size` and `speed` are how many times the unit has been upgraded to grow
+//!   longer and move further, and `attacks` replaces the kind's usual attacks.
+//! - `beaten <node>` records that the campaign level with the given id has been won.
+//!
+//! Blank lines and lines starting with `;` are ignored. Without a `profile.txt`, the player
+//! starts from scratch, with the default roster and no credits.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use super::Unit;
+use deploy::DEFAULT_ROSTER;
+use unit::{Attack, ATTACKS};
+
+const PATH: &'static str = "profile.txt";
+/// The most times a unit's size or speed can be upgraded.
+const MAX_UPGRADES: u16 = 3;
+/// What the first size and speed upgrades cost. Each one after that costs as much again.
+const SIZE_PRICE: u32 = 30;
+const SPEED_PRICE: u32 = 40;
+/// The most attacks a unit can know.
+const MAX_ATTACKS: usize = 4;
+
+/// Something that can be done to a unit in the player's roster.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Upgrade {
+    Size,
+    Speed,
+    /// Teaches the unit the given entry of `unit::ATTACKS`.
+    Learn(usize),
+    /// Makes the unit forget its given attack, to make room for another. This is free.
+    Forget(usize),
+}
+
+/// A unit the player owns, and how it has been changed from the usual unit of its kind.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RosterUnit {
+    pub kind: String,
+    /// How many tiles longer the unit can grow.
+    pub size: u16,
+    /// How many more moves the unit gets each turn.
+    pub speed: u16,
+    /// The names of the unit's attacks.
+    pub attacks: Vec<String>,
+}
+
+impl RosterUnit {
+    pub fn new(kind: &str) -> RosterUnit {
+        let unit = Unit::from_kind(kind, (0, 0)).unwrap();
+        RosterUnit {
+            kind: kind.into(),
+            size: 0,
+            speed: 0,
+            attacks: unit.attacks.iter().map(|attack| attack.name().to_lowercase()).collect(),
+        }
+    }
+
+    /// The unit as it is uploaded into a level.
+    pub fn unit(&self) -> Unit {
+        let mut unit = Unit::from_kind(&self.kind, (0, 0)).unwrap();
+        unit.len_limit += self.size as usize;
+        unit.move_limit += self.speed;
+        unit.moves = unit.move_limit;
+        unit.attacks = self.attacks.iter().map(|name| Attack::from_name(name).unwrap()).collect();
+        unit
+    }
+
+    /// What the upgrade costs, or `None` if it can't be done.
+    pub fn price(&self, upgrade: Upgrade) -> Option<u32> {
+        match upgrade {
+            Upgrade::Size if self.size < MAX_UPGRADES => Some(SIZE_PRICE * (self.size as u32 + 1)),
+            Upgrade::Speed if self.speed < MAX_UPGRADES => {
+                Some(SPEED_PRICE * (self.speed as u32 + 1))
+            },
+            Upgrade::Learn(attack) => {
+                let name = ATTACKS[attack];
+                if self.attacks.len() >= MAX_ATTACKS || self.attacks.iter().any(|a| a == name) {
+                    return None
+                }
+                Attack::price(name)
+            },
+            // Every unit needs at least one attack
+            Upgrade::Forget(i) if i < self.attacks.len() && self.attacks.len() > 1 => Some(0),
+            _ => None,
+        }
+    }
+
+    fn apply(&mut self, upgrade: Upgrade) {
+        match upgrade {
+            Upgrade::Size => self.size += 1,
+            Upgrade::Speed => self.speed += 1,
+            Upgrade::Learn(attack) => self.attacks.push(ATTACKS[attack].into()),
+            Upgrade::Forget(i) => { self.attacks.remove(i); },
+        }
+    }
+}
+
+pub struct Profile {
+    pub credits: u32,
+    /// The units the player owns.
+    pub roster: Vec<RosterUnit>,
+    /// The ids of the campaign levels that have been won, in the order they were first won.
+    pub beaten: Vec<String>,
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile {
+            credits: 0,
+            roster: DEFAULT_ROSTER.iter().map(|kind| RosterUnit::new(kind)).collect(),
+            beaten: vec![],
+        }
+    }
+
+    /// Loads the profile from `profile.txt`, or starts a new one if there isn't one.
+    pub fn load() -> Profile {
+        if Path::new(PATH).exists() {
+            Profile::from_path(PATH)
+        } else {
+            Profile::new()
+        }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Profile {
+        let mut f = File::open(path).unwrap();
+        let mut s = String::new();
+        f.read_to_string(&mut s).unwrap();
+        Profile::from_string(&s)
+    }
+
+    pub fn from_string(s: &str) -> Profile {
+        let mut profile = Profile::new();
+        let mut roster = vec![];
+        for line in s.lines().map(|l| l.trim()) {
+            if line.is_empty() || line.starts_with(";") { continue }
+            let words: Vec<_> = line.split_whitespace().collect();
+            match words[0] {
+                "credits" => {
+                    assert!(words.len() == 2, "expected `credits <amount>`: {}", line);
+                    profile.credits = words[1].parse().unwrap();
+                },
+                "unit" => {
+                    assert!(words.len() % 2 == 0,
+                            "expected `unit <kind> [<property> <value>]...`: {}", line);
+                    assert!(Unit::from_kind(words[1], (0, 0)).is_some(),
+                            "unknown unit kind: {}", words[1]);
+                    let mut unit = RosterUnit::new(words[1]);
+                    for pair in words[2..].chunks(2) {
+                        match pair[0] {
+                            "size" => unit.size = pair[1].parse().unwrap(),
+                            "speed" => unit.speed = pair[1].parse().unwrap(),
+                            "attacks" => {
+                                unit.attacks = pair[1].split(',').map(|name| {
+                                    assert!(Attack::from_name(name).is_some(),
+                                            "unknown attack: {}", name);
+                                    name.into()
+                                }).collect();
+                            },
+                            p => panic!("unknown unit property: {}", p),
+                        }
+                    }
+                    roster.push(unit);
+                },
+                "beaten" => {
+                    assert!(words.len() == 2, "expected `beaten <node>`: {}", line);
+                    profile.beaten.push(words[1].into());
+                },
+                d => panic!("unknown profile directive: {}", d),
+            }
+        }
+        // Units can't be sold, so only profiles from before there were rosters have no units
+        if !roster.is_empty() {
+            profile.roster = roster;
+        }
+        profile
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut f = File::create(PATH)?;
+        write!(f, "{}", self)
+    }
+
+    pub fn has_beaten(&self, node: &str) -> bool {
+        self.beaten.iter().any(|n| n == node)
+    }
+
+    /// Records that the given campaign level has been won.
+    pub fn beat(&mut self, node: &str) {
+        if !self.has_beaten(node) {
+            self.beaten.push(node.into());
+        }
+    }
+
+    /// The units the player can upload into a level, with their upgrades.
+    pub fn roster_units(&self) -> Vec<Unit> {
+        self.roster.iter().map(|unit| unit.unit()).collect()
+    }
+
+    /// Buys a unit of the given kind, if the player can afford it.
+    pub fn buy(&mut self, kind: &str) -> bool {
+        match Unit::price(kind) {
+            Some(price) if price <= self.credits => {
+                self.credits -= price;
+                self.roster.push(RosterUnit::new(kind));
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Upgrades the given unit in the roster, if it can be and the player can afford it.
+    pub fn upgrade(&mut self, unit: usize, upgrade: Upgrade) -> bool {
+        match self.roster[unit].price(upgrade) {
+            Some(price) if price <= self.credits => {
+                self.credits -= price;
+                self.roster[unit].apply(upgrade);
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Writes the profile out in the form it is saved in.
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "; Saved progress. Delete this file to start again.")?;
+        writeln!(f, "credits {}", self.credits)?;
+        for unit in &self.roster {
+            writeln!(f, "unit {} size {} speed {} attacks {}", unit.kind, unit.size, unit.speed,
+                     unit.attacks.join(","))?;
+        }
+        for node in &self.beaten {
+            writeln!(f, "beaten {}", node)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Profile, RosterUnit};
+
+    #[test]
+    fn saved_profiles_load_the_same() {
+        let mut profile = Profile::new();
+        profile.credits = 120;
+        let mut unit = RosterUnit::new("lightning");
+        unit.size = 2;
+        unit.speed = 1;
+        unit.attacks = vec!["dice".into(), "one".into()];
+        profile.roster.push(unit);
+        profile.beat("breach");
+        profile.beat("relay");
+
+        let loaded = Profile::from_string(&profile.to_string());
+        assert_eq!(loaded.credits, profile.credits);
+        assert_eq!(loaded.roster, profile.roster);
+        assert_eq!(loaded.beaten, profile.beaten);
+        assert_eq!(loaded.to_string(), profile.to_string());
+    }
+
+    #[test]
+    fn old_profiles_get_the_default_roster() {
+        let profile = Profile::from_string("; comment\n\ncredits 5\nbeaten breach\n");
+        assert_eq!(profile.credits, 5);
+        assert_eq!(profile.roster, Profile::new().roster);
+        assert!(profile.has_beaten("breach"));
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown attack: zap")]
+    fn unknown_attacks_are_errors() {
+        Profile::from_string("unit hack attacks slice,zap\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown unit property: colour")]
+    fn unknown_unit_properties_are_errors() {
+        Profile::from_string("unit hack colour red\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown profile directive: gold")]
+    fn unknown_directives_are_errors() {
+        Profile::from_string("gold 5\n");
+    }
+}
@@ -0,0 +1,929 @@
+//! The screens around a match: the title menu, level select, campaign map, story, shop and
+//! upgrades, deployment, pause and game over screens, plus the level editor.
+//!
+//! `App` owns whichever screen is showing, including the `Game` itself while one is being
+//! played, and passes input on to it.
+
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use piston::input::{Button, Key, MouseButton};
+use graphics::{Context, clear, line, rectangle};
+use opengl_graphics::GlGraphics;
+
+use super::{Game, Unit};
+use campaign::Campaign;
+use deploy::{self, Deployment};
+use editor::{self, Editor};
+use game::Command;
+use keys::{Bindings, Control};
+use level::Level;
+use pad::{Pad, PadEvent, PadButton};
+use profile::{Profile, Upgrade};
+use hud;
+use text::{Font, Text, Align};
+use unit::{Attack, ATTACKS, KINDS};
+
+const ITEM_WIDTH: f64 = 240.0;
+const ITEM_HEIGHT: f64 = 24.0;
+const ITEM_GAP: f64 = 6.0;
+/// How far down the window the first menu item is.
+const MENU_TOP: f64 = 180.0;
+const NODE_WIDTH: f64 = 110.0;
+/// How far apart the columns and rows of the campaign map are.
+const COLUMN_WIDTH: f64 = 140.0;
+const ROW_HEIGHT: f64 = 50.0;
+/// How far down the window the campaign map starts.
+const MAP_TOP: f64 = 160.0;
+const LINE_HEIGHT: f64 = 20.0;
+/// How many menu items fit in a column above the items along the bottom.
+const COLUMN_ROWS: usize = 8;
+
+pub enum Screen {
+    Title { selected: usize },
+    /// Choosing a level to play, or to edit if `edit` is set.
+    LevelSelect { levels: Vec<PathBuf>, selected: usize, edit: bool },
+    /// The campaign map, with the unlocked levels to choose from.
+    Map { selected: usize },
+    /// The story before the current campaign level, or after it has been won if `after` is set.
+    Story { after: bool },
+    /// Buying units for the player's roster with the credits they have earned.
+    Shop { selected: usize },
+    /// Choosing a unit from the player's roster to upgrade.
+    Roster { selected: usize },
+    /// Upgrading the given unit of the player's roster.
+    Upgrade { unit: usize, selected: usize },
+    /// Placing units before the game starts.
+    Deploying(Deployment),
+    Playing(Game),
+    Paused { game: Game, selected: usize },
+    /// The match has been won by the given team.
+    Over { game: Game, winner: u16 },
+    Editor(Editor),
+}
+
+/// Something that can be chosen from a menu.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Item {
+    Play,
+    Campaign,
+    Edit,
+    Quit,
+    /// The given entry of the level select screen's list.
+    Level(usize),
+    NewLevel,
+    /// The given node of the campaign.
+    Node(usize),
+    Continue,
+    Shop,
+    /// Buys a unit of the given entry of `unit::KINDS`.
+    Buy(usize),
+    Roster,
+    /// The given unit of the player's roster.
+    Unit(usize),
+    Upgrade(Upgrade),
+    Back,
+    Resume,
+    Restart,
+    Menu,
+}
+
+pub struct App {
+    pub screen: Screen,
+    /// The level being played, which restarting it goes back to. Networked games can't be
+    /// restarted, so they don't have one.
+    pub level: Option<Level>,
+    /// The editor the level came from, if it is being play-tested.
+    pub editing: Option<Editor>,
+    /// The campaign, while its map is showing or one of its levels is being played.
+    pub campaign: Option<Campaign>,
+    /// The campaign level being played, or whose story is showing.
+    node: Option<usize>,
+    pub profile: Profile,
+    /// The keys used by local players, given to every game that is started.
+    bindings: Bindings,
+    pub font: Font,
+    pub mouse: (f64, f64),
+    /// Turns the gamepad's sticks into moves around the menus.
+    pad: Pad,
+    /// Set when the player chooses to quit, at which point the window should be closed.
+    pub quit: bool,
+    /// The size of the window when it was last drawn.
+    view_size: [f64; 2],
+}
+
+/// Every level in the `levels` directory, sorted by name.
+fn find_levels() -> Vec<PathBuf> {
+    let mut levels: Vec<_> = fs::read_dir("levels").unwrap()
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().map_or(false, |ext| ext == "sunrise"))
+        .collect();
+    levels.sort();
+    levels
+}
+
+fn level_name(path: &Path) -> String {
+    path.file_stem().unwrap().to_string_lossy().into_owned()
+}
+
+/// A file in the `levels` directory for a new level to be saved to.
+fn new_level_path() -> PathBuf {
+    (1..).map(|i| PathBuf::from(format!("levels/untitled{}.sunrise", i)))
+         .find(|path| !path.exists())
+         .unwrap()
+}
+
+/// Where the `i`th item of a menu goes in a window of the given size.
+fn item_rect(i: usize, size: [f64; 2]) -> [f64; 4] {
+    [(size[0] - ITEM_WIDTH) / 2.0, MENU_TOP + i as f64 * (ITEM_HEIGHT + ITEM_GAP),
+     ITEM_WIDTH, ITEM_HEIGHT]
+}
+
+/// Where the `i`th of the `n` menu items in a row along the bottom of the campaign map and story
+/// screens goes.
+fn bottom_rect(i: usize, n: usize, size: [f64; 2]) -> [f64; 4] {
+    let width = n as f64 * (ITEM_WIDTH + ITEM_GAP) - ITEM_GAP;
+    [(size[0] - width) / 2.0 + i as f64 * (ITEM_WIDTH + ITEM_GAP), size[1] - ITEM_HEIGHT - 20.0,
+     ITEM_WIDTH, ITEM_HEIGHT]
+}
+
+/// Where the `row`th item of the `column`th of `n` columns of menu items goes.
+fn column_rect(column: usize, n: usize, row: usize, size: [f64; 2]) -> [f64; 4] {
+    let mut rect = bottom_rect(column, n, size);
+    rect[1] = MENU_TOP + row as f64 * (ITEM_HEIGHT + ITEM_GAP);
+    rect
+}
+
+/// The name of the given entry of `unit::ATTACKS`.
+fn attack_name(attack: &str) -> &'static str {
+    Attack::from_name(attack).unwrap().name()
+}
+
+/// Where the given node goes on the campaign map in a window of the given size.
+fn node_rect(campaign: &Campaign, node: usize, size: [f64; 2]) -> [f64; 4] {
+    let columns = campaign.nodes.iter().map(|n| n.pos.0).max().unwrap() + 1;
+    let left = (size[0] - (columns - 1) as f64 * COLUMN_WIDTH - NODE_WIDTH) / 2.0;
+    let (x, y) = campaign.nodes[node].pos;
+    [left + x as f64 * COLUMN_WIDTH, MAP_TOP + y as f64 * ROW_HEIGHT, NODE_WIDTH, ITEM_HEIGHT]
+}
+
+impl App {
+    pub fn new() -> App {
+        App {
+            screen: Screen::Title { selected: 0 },
+            level: None,
+            editing: None,
+            campaign: None,
+            node: None,
+            profile: Profile::load(),
+            bindings: Bindings::load(),
+            font: Font::from_path("./assets/font.txt"),
+            mouse: (0.0, 0.0),
+            pad: Pad::new(),
+            quit: false,
+            view_size: [0.0, 0.0],
+        }
+    }
+
+    /// Starts playing `level` straight away, skipping the menus.
+    pub fn playing(level: Level) -> App {
+        let mut app = App::new();
+        app.play(level);
+        app
+    }
+
+    /// Starts playing a networked game straight away. There's no placing units first, so the
+    /// upload zones are just floor.
+    pub fn playing_online(mut game: Game) -> App {
+        let mut app = App::new();
+        game.bindings = app.bindings.clone();
+        deploy::clear_zones(&mut game);
+        app.start(game);
+        app
+    }
+
+    /// Starts playing `level`, placing units on its upload zones first if it has any. In the
+    /// campaign, the units come from the player's roster.
+    pub fn play(&mut self, level: Level) {
+        self.level = Some(level.clone());
+        let roster = if self.node.is_some() {
+            self.profile.roster_units()
+        } else {
+            deploy::default_roster()
+        };
+        let mut game = Game::new(level);
+        game.bindings = self.bindings.clone();
+        match Deployment::new(game, roster) {
+            Ok(deployment) => self.screen = Screen::Deploying(deployment),
+            Err(game) => self.start(game),
+        }
+    }
+
+    fn start(&mut self, mut game: Game) {
+        game.select_team(0);
+        self.screen = Screen::Playing(game);
+    }
+
+    /// Shows the campaign map, with the first level that hasn't been beaten yet selected.
+    fn show_map(&mut self) {
+        self.screen = Screen::Map { selected: 0 };
+        let items = self.items();
+        let campaign = self.campaign.as_ref().unwrap();
+        let profile = &self.profile;
+        let selected = items.iter().position(|&item| match item {
+            Item::Node(n) => !profile.has_beaten(&campaign.nodes[n].id),
+            _ => false,
+        });
+        self.screen = Screen::Map { selected: selected.unwrap_or(0) };
+    }
+
+    /// Plays the current campaign level.
+    fn play_node(&mut self) {
+        let level = {
+            let campaign = self.campaign.as_ref().unwrap();
+            Level::from_path(&campaign.nodes[self.node.unwrap()].level)
+        };
+        self.play(level);
+    }
+
+    /// Whether the game that has just finished was a campaign level won by the player.
+    fn won_node(&self) -> bool {
+        match self.screen {
+            Screen::Over { ref game, winner } => {
+                self.node.is_some() && game.teams[winner as usize].is_local()
+            },
+            _ => false,
+        }
+    }
+
+    /// Starts the current level again from the beginning, if it can be.
+    fn restart(&mut self) -> bool {
+        match self.level.clone() {
+            Some(level) => {
+                self.play(level);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// The game being deployed, played, paused or just finished, if there is one.
+    fn game_mut(&mut self) -> Option<&mut Game> {
+        match self.screen {
+            Screen::Deploying(ref mut deployment) => Some(&mut deployment.game),
+            Screen::Playing(ref mut game)
+            | Screen::Paused { ref mut game, .. }
+            | Screen::Over { ref mut game, .. } => Some(game),
+            Screen::Title { .. } | Screen::LevelSelect { .. } | Screen::Map { .. }
+            | Screen::Story { .. } | Screen::Shop { .. } | Screen::Roster { .. }
+            | Screen::Upgrade { .. } | Screen::Editor(_) => None,
+        }
+    }
+
+    /// The current screen's menu items.
+    fn items(&self) -> Vec<Item> {
+        let mut items = match self.screen {
+            Screen::Title { .. } => vec![Item::Play, Item::Campaign, Item::Edit, Item::Quit],
+            Screen::LevelSelect { ref levels, edit, .. } => {
+                let mut items: Vec<_> = (0..levels.len()).map(Item::Level).collect();
+                if edit {
+                    items.push(Item::NewLevel);
+                }
+                items.push(Item::Back);
+                items
+            },
+            Screen::Map { .. } => {
+                let campaign = self.campaign.as_ref().unwrap();
+                let mut items: Vec<_> = (0..campaign.nodes.len())
+                    .filter(|&n| campaign.is_unlocked(n, &self.profile))
+                    .map(Item::Node)
+                    .collect();
+                items.push(Item::Shop);
+                items.push(Item::Back);
+                items
+            },
+            Screen::Story { .. } => vec![Item::Continue],
+            Screen::Shop { .. } => {
+                let mut items: Vec<_> = (0..KINDS.len()).map(Item::Buy).collect();
+                items.push(Item::Roster);
+                items.push(Item::Back);
+                items
+            },
+            Screen::Roster { .. } => {
+                let mut items: Vec<_> = (0..self.profile.roster.len()).map(Item::Unit).collect();
+                items.push(Item::Back);
+                items
+            },
+            Screen::Upgrade { unit, .. } => {
+                // Attacks the unit already knows can only be forgotten
+                let attacks = &self.profile.roster[unit].attacks;
+                let mut items = vec![Item::Upgrade(Upgrade::Size), Item::Upgrade(Upgrade::Speed)];
+                items.extend((0..ATTACKS.len())
+                    .filter(|&a| !attacks.iter().any(|name| name == ATTACKS[a]))
+                    .map(|a| Item::Upgrade(Upgrade::Learn(a))));
+                items.extend((0..attacks.len()).map(|i| Item::Upgrade(Upgrade::Forget(i))));
+                items.push(Item::Back);
+                items
+            },
+            Screen::Deploying(_) | Screen::Playing(_) | Screen::Editor(_) => vec![],
+            Screen::Paused { .. } => vec![Item::Resume, Item::Restart, Item::Menu],
+            Screen::Over { .. } => vec![Item::Restart, Item::Menu],
+        };
+        if self.level.is_none() {
+            items.retain(|&item| item != Item::Restart);
+        }
+        items
+    }
+
+    fn label(&self, item: Item) -> String {
+        match item {
+            Item::Play => "Play".into(),
+            Item::Campaign => "Campaign".into(),
+            Item::Edit => "Level editor".into(),
+            Item::Quit => "Quit".into(),
+            Item::Level(i) => match self.screen {
+                Screen::LevelSelect { ref levels, .. } => level_name(&levels[i]),
+                _ => unreachable!(),
+            },
+            Item::NewLevel => "New level".into(),
+            Item::Node(n) => self.campaign.as_ref().unwrap().nodes[n].title.clone(),
+            Item::Continue => "Continue".into(),
+            Item::Shop => "Shop".into(),
+            Item::Buy(kind) => {
+                let name = Unit::from_kind(KINDS[kind], (0, 0)).unwrap().name;
+                format!("{} - {}", name, Unit::price(KINDS[kind]).unwrap())
+            },
+            Item::Roster => "Upgrade units".into(),
+            Item::Unit(i) => self.profile.roster[i].unit().name.into(),
+            Item::Upgrade(upgrade) => {
+                let unit = match self.screen {
+                    Screen::Upgrade { unit, .. } => &self.profile.roster[unit],
+                    _ => unreachable!(),
+                };
+                let name = match upgrade {
+                    Upgrade::Size => "Size".into(),
+                    Upgrade::Speed => "Speed".into(),
+                    Upgrade::Learn(a) => format!("Learn {}", attack_name(ATTACKS[a])),
+                    Upgrade::Forget(i) => format!("Forget {}", attack_name(&unit.attacks[i])),
+                };
+                match unit.price(upgrade) {
+                    Some(0) | None => name,
+                    Some(price) => format!("{} - {}", name, price),
+                }
+            },
+            Item::Back => "Back".into(),
+            Item::Resume => "Resume".into(),
+            Item::Restart => match self.screen {
+                Screen::Over { .. } => "Play again".into(),
+                _ => "Restart".into(),
+            },
+            Item::Menu if self.editing.is_some() => "Back to editor".into(),
+            Item::Menu if self.won_node() => "Continue".into(),
+            Item::Menu if self.node.is_some() => "Back to map".into(),
+            Item::Menu => "Quit to menu".into(),
+        }
+    }
+
+    fn selected_mut(&mut self) -> Option<&mut usize> {
+        match self.screen {
+            Screen::Title { ref mut selected }
+            | Screen::LevelSelect { ref mut selected, .. }
+            | Screen::Map { ref mut selected }
+            | Screen::Shop { ref mut selected }
+            | Screen::Roster { ref mut selected }
+            | Screen::Upgrade { ref mut selected, .. }
+            | Screen::Paused { ref mut selected, .. } => Some(selected),
+            Screen::Story { .. } | Screen::Deploying(_) | Screen::Playing(_) | Screen::Over { .. }
+            | Screen::Editor(_) => None,
+        }
+    }
+
+    fn choose(&mut self, item: Item) {
+        let screen = mem::replace(&mut self.screen, Screen::Title { selected: 0 });
+        match (screen, item) {
+            (_, Item::Play) => {
+                self.screen = Screen::LevelSelect { levels: find_levels(), selected: 0,
+                                                    edit: false };
+            },
+            (_, Item::Campaign) => {
+                self.campaign = Some(Campaign::load());
+                self.show_map();
+            },
+            (_, Item::Edit) => {
+                self.screen = Screen::LevelSelect { levels: find_levels(), selected: 0,
+                                                    edit: true };
+            },
+            (screen, Item::Quit) => {
+                self.screen = screen;
+                self.quit = true;
+            },
+            (Screen::LevelSelect { levels, edit: false, .. }, Item::Level(i)) => {
+                self.play(Level::from_path(&levels[i]));
+            },
+            (Screen::LevelSelect { mut levels, edit: true, .. }, Item::Level(i)) => {
+                self.screen = Screen::Editor(Editor::open(levels.swap_remove(i)));
+            },
+            (_, Item::NewLevel) => {
+                self.screen = Screen::Editor(Editor::new(Level::new(14, 11), new_level_path()));
+            },
+            (_, Item::Node(n)) => {
+                self.node = Some(n);
+                if self.campaign.as_ref().unwrap().nodes[n].before.is_empty() {
+                    self.play_node();
+                } else {
+                    self.screen = Screen::Story { after: false };
+                }
+            },
+            (Screen::Story { after: false }, Item::Continue) => self.play_node(),
+            (_, Item::Continue) => {
+                self.node = None;
+                self.show_map();
+            },
+            (_, Item::Shop) => self.screen = Screen::Shop { selected: 0 },
+            (screen, Item::Buy(kind)) => {
+                self.screen = screen;
+                if self.profile.buy(KINDS[kind]) {
+                    if let Err(e) = self.profile.save() {
+                        eprintln!("couldn't save profile: {}", e);
+                    }
+                }
+            },
+            (Screen::Shop { .. }, Item::Back) => self.show_map(),
+            (_, Item::Roster) => self.screen = Screen::Roster { selected: 0 },
+            (_, Item::Unit(i)) => self.screen = Screen::Upgrade { unit: i, selected: 0 },
+            (Screen::Upgrade { unit, selected }, Item::Upgrade(upgrade)) => {
+                self.screen = Screen::Upgrade { unit: unit, selected: selected };
+                if self.profile.upgrade(unit, upgrade) {
+                    if let Err(e) = self.profile.save() {
+                        eprintln!("couldn't save profile: {}", e);
+                    }
+                }
+                // Learning and forgetting attacks changes what there is to choose from
+                let len = self.items().len();
+                if let Some(selected) = self.selected_mut() {
+                    *selected = (*selected).min(len - 1);
+                }
+            },
+            (Screen::Roster { .. }, Item::Back) => {
+                self.screen = Screen::Shop { selected: KINDS.len() };
+            },
+            (Screen::Upgrade { unit, .. }, Item::Back) => {
+                self.screen = Screen::Roster { selected: unit };
+            },
+            (Screen::Paused { game, .. }, Item::Resume) => self.screen = Screen::Playing(game),
+            (screen, Item::Restart) => if !self.restart() {
+                self.screen = screen;
+            },
+            // Going back to the title menu (or the editor, or the campaign map) leaves the level
+            // and any network game behind
+            (screen, _) => {
+                self.level = None;
+                if let Some(editor) = self.editing.take() {
+                    self.screen = Screen::Editor(editor);
+                } else if let Some(node) = self.node {
+                    // Winning a level leads to its story, if it has any, and then back to the map
+                    self.screen = screen;
+                    let story = !self.campaign.as_ref().unwrap().nodes[node].after.is_empty();
+                    if self.won_node() && story {
+                        self.screen = Screen::Story { after: true };
+                    } else {
+                        self.node = None;
+                        self.show_map();
+                    }
+                } else {
+                    self.campaign = None;
+                }
+            },
+        }
+    }
+
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::Pause => {
+                if let Screen::Playing(game) = mem::replace(&mut self.screen,
+                                                            Screen::Title { selected: 0 }) {
+                    self.screen = Screen::Paused { game: game, selected: 0 };
+                }
+            },
+            Command::Restart => { self.restart(); },
+            Command::QuitToMenu => self.choose(Item::Menu),
+        }
+    }
+
+    /// Goes to the game over screen if somebody has won.
+    fn check_over(&mut self) {
+        let winner = match self.screen {
+            Screen::Playing(ref game) => game.winner(),
+            _ => None,
+        };
+        if let Some(winner) = winner {
+            if let Screen::Playing(game) = mem::replace(&mut self.screen,
+                                                        Screen::Title { selected: 0 }) {
+                self.screen = Screen::Over { game: game, winner: winner };
+            }
+            // Winning a campaign level for the first time earns its reward, and the player keeps
+            // whatever they picked up. Replaying it earns nothing more.
+            if self.won_node() {
+                let node = &self.campaign.as_ref().unwrap().nodes[self.node.unwrap()];
+                if !self.profile.has_beaten(&node.id) {
+                    self.profile.credits += node.reward;
+                    if let Screen::Over { ref game, winner } = self.screen {
+                        self.profile.credits += game.teams[winner as usize].credits;
+                    }
+                }
+                self.profile.beat(&node.id);
+                if let Err(e) = self.profile.save() {
+                    eprintln!("couldn't save profile: {}", e);
+                }
+            }
+        }
+    }
+
+    pub fn handle_press(&mut self, button: Button) {
+        let mut request = None;
+        if let Screen::Editor(ref mut editor) = self.screen {
+            request = Some(editor.handle_press(button));
+        }
+        if let Some(request) = request {
+            match request {
+                Some(editor::Request::PlayTest) => {
+                    if let Screen::Editor(editor) = mem::replace(&mut self.screen,
+                                                                 Screen::Title { selected: 0 }) {
+                        let level = editor.level.clone();
+                        self.editing = Some(editor);
+                        self.play(level);
+                    }
+                },
+                Some(editor::Request::Exit) => self.screen = Screen::Title { selected: 0 },
+                None => {},
+            }
+            return
+        }
+
+        let mut request = None;
+        if let Screen::Deploying(ref mut deployment) = self.screen {
+            request = Some(deployment.handle_press(button));
+        }
+        if let Some(request) = request {
+            self.handle_deploy_request(request);
+            return
+        }
+
+        let mut command = None;
+        if let Screen::Playing(ref mut game) = self.screen {
+            command = Some(game.handle_press(button));
+        }
+        if let Some(command) = command {
+            if let Some(command) = command {
+                self.handle_command(command);
+            }
+            self.check_over();
+            return
+        }
+
+        let items = self.items();
+        let len = items.len();
+        let mouse = self.mouse;
+        let size = self.view_size;
+        let mut chosen = None;
+        match button {
+            Button::Keyboard(Key::Escape) => {
+                chosen = match self.screen {
+                    Screen::Paused { .. } => Some(Item::Resume),
+                    Screen::LevelSelect { .. } | Screen::Map { .. } | Screen::Shop { .. }
+                    | Screen::Roster { .. } | Screen::Upgrade { .. } => Some(Item::Back),
+                    Screen::Story { .. } => Some(Item::Continue),
+                    _ => None,
+                };
+            },
+            Button::Keyboard(Key::Up) => if let Some(selected) = self.selected_mut() {
+                *selected = (*selected + len - 1) % len;
+            },
+            Button::Keyboard(Key::Down) => if let Some(selected) = self.selected_mut() {
+                *selected = (*selected + 1) % len;
+            },
+            Button::Keyboard(Key::Return) => {
+                chosen = Some(items[self.selected_mut().map_or(0, |s| *s)]);
+            },
+            Button::Mouse(MouseButton::Left) => {
+                chosen = (0..len).find(|&i| hud::is_inside(self.rect(i, items[i], size), mouse))
+                                 .map(|i| items[i]);
+            },
+            _ => {},
+        }
+        if let Some(item) = chosen {
+            self.choose(item);
+        }
+    }
+
+    fn handle_deploy_request(&mut self, request: Option<deploy::Request>) {
+        match request {
+            Some(deploy::Request::Start) => {
+                let screen = mem::replace(&mut self.screen, Screen::Title { selected: 0 });
+                if let Screen::Deploying(deployment) = screen {
+                    self.start(deployment.finish());
+                }
+            },
+            Some(deploy::Request::Exit) => self.choose(Item::Menu),
+            None => {},
+        }
+    }
+
+    pub fn handle_pad(&mut self, event: PadEvent) {
+        let mut command = None;
+        if let Screen::Playing(ref mut game) = self.screen {
+            command = Some(game.handle_pad(event));
+        }
+        if let Some(command) = command {
+            if let Some(command) = command {
+                self.handle_command(command);
+            }
+            self.check_over();
+            return
+        }
+
+        let mut request = None;
+        if let Screen::Deploying(ref mut deployment) = self.screen {
+            request = Some(deployment.handle_pad(event));
+        }
+        if let Some(request) = request {
+            self.handle_deploy_request(request);
+            return
+        }
+
+        // In the menus, the gamepad works like the keys for moving around
+        let key = match event {
+            PadEvent::Press(PadButton::A) => Some(Key::Return),
+            PadEvent::Press(PadButton::B) | PadEvent::Press(PadButton::Start) => Some(Key::Escape),
+            event => match self.pad.control(event) {
+                Some(Control::Up) => Some(Key::Up),
+                Some(Control::Down) => Some(Key::Down),
+                Some(Control::Left) => Some(Key::Left),
+                Some(Control::Right) => Some(Key::Right),
+                _ => None,
+            },
+        };
+        if let Some(key) = key {
+            self.handle_press(Button::Keyboard(key));
+        }
+    }
+
+    pub fn handle_resize(&mut self, width: u32, height: u32) {
+        let size = [width as f64, height as f64];
+        self.view_size = size;
+        if let Some(game) = self.game_mut() {
+            game.view_size = size;
+            game.camera.fit(&game.grid, size);
+        }
+    }
+
+    pub fn handle_release(&mut self, button: Button) {
+        if let Screen::Editor(ref mut editor) = self.screen {
+            editor.handle_release(button);
+        }
+        if let Screen::Deploying(ref mut deployment) = self.screen {
+            deployment.handle_release(button);
+            return
+        }
+        if let Some(game) = self.game_mut() {
+            game.handle_release(button);
+        }
+    }
+
+    /// Called when the mouse enters or leaves the window.
+    pub fn handle_cursor(&mut self, inside: bool) {
+        if let Some(game) = self.game_mut() {
+            game.hovering = inside;
+        }
+    }
+
+    pub fn handle_scroll(&mut self, amount: f64) {
+        match self.screen {
+            Screen::Deploying(ref mut deployment) => deployment.handle_scroll(amount),
+            Screen::Playing(ref mut game) => game.handle_scroll(amount),
+            Screen::Editor(ref mut editor) => editor.handle_scroll(amount),
+            _ => {},
+        }
+    }
+
+    pub fn handle_mouse(&mut self, x: f64, y: f64) {
+        self.mouse = (x, y);
+        match self.screen {
+            Screen::Playing(ref mut game) => {
+                game.handle_mouse(x, y);
+                return
+            },
+            Screen::Editor(ref mut editor) => {
+                editor.handle_mouse(x, y);
+                return
+            },
+            Screen::Deploying(ref mut deployment) => {
+                deployment.handle_mouse(x, y);
+                return
+            },
+            _ => {},
+        }
+        let size = self.view_size;
+        let items = self.items();
+        let hovered = (0..items.len())
+            .find(|&i| hud::is_inside(self.rect(i, items[i], size), (x, y)));
+        if let (Some(item), Some(selected)) = (hovered, self.selected_mut()) {
+            *selected = item;
+        }
+    }
+
+    pub fn handle_frame(&mut self) {
+        // Only a game that is being played moves on, so the AI stops while the game is paused
+        if let Screen::Playing(ref mut game) = self.screen {
+            game.handle_frame();
+        }
+        self.check_over();
+    }
+
+    /// Where the `i`th item of the current screen's menu, `item`, goes in a window of the given
+    /// size.
+    fn rect(&self, i: usize, item: Item, size: [f64; 2]) -> [f64; 4] {
+        match (&self.screen, item) {
+            (&Screen::Map { .. }, Item::Node(n)) => {
+                node_rect(self.campaign.as_ref().unwrap(), n, size)
+            },
+            (&Screen::Map { .. }, Item::Shop) => bottom_rect(0, 2, size),
+            (&Screen::Map { .. }, _) => bottom_rect(1, 2, size),
+            (&Screen::Story { .. }, _) => bottom_rect(0, 1, size),
+            (&Screen::Roster { .. }, Item::Unit(i)) => {
+                let columns = (self.profile.roster.len() + COLUMN_ROWS - 1) / COLUMN_ROWS;
+                column_rect(i / COLUMN_ROWS, columns, i % COLUMN_ROWS, size)
+            },
+            // What can be bought goes on the left and the attacks to forget on the right
+            (&Screen::Upgrade { .. }, Item::Upgrade(Upgrade::Forget(a))) => {
+                column_rect(1, 2, a, size)
+            },
+            (&Screen::Upgrade { .. }, Item::Upgrade(_)) => column_rect(0, 2, i, size),
+            (&Screen::Roster { .. }, _) | (&Screen::Upgrade { .. }, _) => bottom_rect(0, 1, size),
+            _ => item_rect(i, size),
+        }
+    }
+
+    /// Draws the links between the campaign's levels, and the levels that are still locked.
+    fn draw_map(&self, c: &Context, gl: &mut GlGraphics) {
+        let campaign = self.campaign.as_ref().unwrap();
+        let size = c.get_view_size();
+        let centre = |n| {
+            let rect = node_rect(campaign, n, size);
+            (rect[0] + rect[2] / 2.0, rect[1] + rect[3] / 2.0)
+        };
+        for (a, node) in campaign.nodes.iter().enumerate() {
+            for &b in node.links.iter().filter(|&&b| b > a) {
+                let ((x1, y1), (x2, y2)) = (centre(a), centre(b));
+                line([0.5, 0.5, 0.5, 1.0], 1.0, [x1, y1, x2, y2], c.transform, gl);
+            }
+        }
+        for (n, node) in campaign.nodes.iter().enumerate() {
+            let rect = node_rect(campaign, n, size);
+            rectangle([0.0, 0.0, 0.0, 1.0], rect, c.transform, gl);
+            if !campaign.is_unlocked(n, &self.profile) {
+                rectangle([1.0, 1.0, 1.0, 0.05], rect, c.transform, gl);
+                Text::new([0.4, 0.4, 0.4, 1.0]).scale(2.0).align(Align::Centre)
+                    .draw(&node.title, &self.font, rect[0] + rect[2] / 2.0, rect[1] + 5.0, c, gl);
+            } else if self.profile.has_beaten(&node.id) {
+                rectangle([0.3, 1.0, 0.4, 1.0], [rect[0], rect[1] + rect[3], rect[2], 2.0],
+                          c.transform, gl);
+            }
+        }
+    }
+
+    fn draw_menu(&self, c: &Context, gl: &mut GlGraphics) {
+        let selected = match self.screen {
+            Screen::Title { selected }
+            | Screen::LevelSelect { selected, .. }
+            | Screen::Map { selected }
+            | Screen::Shop { selected }
+            | Screen::Roster { selected }
+            | Screen::Upgrade { selected, .. }
+            | Screen::Paused { selected, .. } => Some(selected),
+            Screen::Story { .. } | Screen::Deploying(_) | Screen::Playing(_) | Screen::Over { .. }
+            | Screen::Editor(_) => None,
+        };
+        let size = c.get_view_size();
+        for (i, &item) in self.items().iter().enumerate() {
+            let rect = self.rect(i, item, size);
+            let alpha = if selected.map_or(true, |s| s == i) { 0.4 } else { 0.1 };
+            rectangle([1.0, 1.0, 1.0, alpha], rect, c.transform, gl);
+            Text::new([1.0, 1.0, 1.0, 1.0]).scale(2.0).align(Align::Centre)
+                .draw(&self.label(item), &self.font, rect[0] + rect[2] / 2.0, rect[1] + 5.0, c, gl);
+        }
+    }
+
+    /// Draws a heading and a subheading above the menu.
+    fn draw_title(&self, title: &str, subtitle: &str, colour: [f32; 4], c: &Context,
+                  gl: &mut GlGraphics) {
+        let size = c.get_view_size();
+        Text::new(colour).scale(6.0).align(Align::Centre)
+            .draw(title, &self.font, size[0] / 2.0, 60.0, c, gl);
+        Text::new([0.6, 0.6, 0.6, 1.0]).scale(2.0).align(Align::Centre)
+            .draw(subtitle, &self.font, size[0] / 2.0, 120.0, c, gl);
+    }
+
+    pub fn draw(&mut self, c: &Context, gl: &mut GlGraphics) {
+        self.view_size = c.get_view_size();
+        let size = self.view_size;
+        match self.screen {
+            Screen::Playing(ref mut game)
+            | Screen::Paused { ref mut game, .. }
+            | Screen::Over { ref mut game, .. } => {
+                game.draw(c, gl);
+            },
+            Screen::Editor(ref mut editor) => {
+                editor.draw(&self.font, c, gl);
+                return
+            },
+            Screen::Deploying(ref mut deployment) => {
+                deployment.draw(c, gl);
+                return
+            },
+            _ => clear([0.0, 0.0, 0.0, 1.0], gl),
+        }
+
+        let white = [1.0, 1.0, 1.0, 1.0];
+        match self.screen {
+            Screen::Title { .. } => self.draw_title("SLYDOT", "The Sunrise Event", white, c, gl),
+            Screen::LevelSelect { edit: false, .. } => {
+                self.draw_title("Levels", "Choose a level", white, c, gl);
+            },
+            Screen::LevelSelect { edit: true, .. } => {
+                self.draw_title("Editor", "Choose a level to edit", white, c, gl);
+            },
+            Screen::Map { .. } => {
+                self.draw_title("Campaign", "Choose a level", white, c, gl);
+                self.draw_map(c, gl);
+            },
+            Screen::Shop { .. } => {
+                let credits = format!("{} credits", self.profile.credits);
+                self.draw_title("Shop", &credits, white, c, gl);
+                // What the player already has
+                let mut owned: Vec<String> = vec![];
+                for &kind in KINDS.iter() {
+                    let count = self.profile.roster.iter().filter(|u| u.kind == kind).count();
+                    if count > 0 {
+                        let name = Unit::from_kind(kind, (0, 0)).unwrap().name;
+                        owned.push(format!("{} x{}", name, count));
+                    }
+                }
+                Text::new([0.6, 0.6, 0.6, 1.0]).scale(2.0).align(Align::Centre)
+                    .draw(&format!("Roster: {}", owned.join(", ")), &self.font, size[0] / 2.0,
+                          size[1] - 40.0, c, gl);
+            },
+            Screen::Roster { selected } => {
+                // The stats of the unit that is selected, or the player's credits
+                let subtitle = match self.profile.roster.get(selected) {
+                    Some(unit) => {
+                        let attacks: Vec<_> = unit.attacks.iter().map(|a| attack_name(a)).collect();
+                        let stats = unit.unit();
+                        format!("Size {} Moves {}: {}", stats.len_limit, stats.move_limit,
+                                attacks.join(", "))
+                    },
+                    None => format!("{} credits", self.profile.credits),
+                };
+                self.draw_title("Roster", &subtitle, white, c, gl);
+            },
+            Screen::Upgrade { unit, .. } => {
+                let unit = self.profile.roster[unit].unit();
+                let subtitle = format!("Size {} Moves {} - {} credits", unit.len_limit,
+                                       unit.move_limit, self.profile.credits);
+                self.draw_title(unit.name, &subtitle, white, c, gl);
+            },
+            Screen::Story { after } => {
+                let node = &self.campaign.as_ref().unwrap().nodes[self.node.unwrap()];
+                let subtitle = if after { "Complete" } else { "" };
+                self.draw_title(&node.title, subtitle, white, c, gl);
+                let lines = if after { &node.after } else { &node.before };
+                for (i, text) in lines.iter().enumerate() {
+                    let y = 150.0 + i as f64 * LINE_HEIGHT;
+                    Text::new(white).scale(2.0).align(Align::Centre)
+                        .draw(text, &self.font, size[0] / 2.0, y, c, gl);
+                }
+            },
+            Screen::Deploying(_) | Screen::Playing(_) | Screen::Editor(_) => return,
+            Screen::Paused { .. } => {
+                rectangle([0.0, 0.0, 0.0, 0.7], [0.0, 0.0, size[0], size[1]], c.transform, gl);
+                self.draw_title("Paused", "", white, c, gl);
+            },
+            Screen::Over { ref game, winner } => {
+                rectangle([0.0, 0.0, 0.0, 0.7], [0.0, 0.0, size[0], size[1]], c.transform, gl);
+                let team = &game.teams[winner as usize];
+                let colour = [team.colour[0], team.colour[1], team.colour[2], 1.0];
+                // Only talk about victory and defeat when one side is being played here
+                let local = game.teams.iter().filter(|t| t.is_local()).count();
+                let title = if local == 0 || (local > 1 && team.is_local()) {
+                    "Game over"
+                } else if team.is_local() {
+                    "Victory"
+                } else {
+                    "Defeat"
+                };
+                self.draw_title(title, &format!("{} wins", team.name), colour, c, gl);
+            },
+        }
+        self.draw_menu(c, gl);
+    }
+}
@@ -0,0 +1,249 @@
+//! Things levels can make happen while they are being played.
+//!
+//! A level's script is a list of triggers, each with an effect that happens the first time the
+//! trigger goes off. Triggers only go off once, and their effects happen once whatever set them
+//! off has finished, so a trigger set off by a move takes effect after the move. Nothing that
+//! happened before a trigger went off can be undone.
+//!
+//! The script finds out what has happened the same way anything else does, from the game's
+//! `events`.
+
+use std::fmt;
+
+use super::{Game, Unit};
+use anim;
+use event::Event;
+use grid::{Cell, CELLS};
+
+/// Something that can happen during a game for a level's script to react to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    /// The given round has started, before anyone has had their turn.
+    Round(u32),
+    /// The given team's turn has started.
+    Turn(u16),
+    /// One of the given team's units has been destroyed.
+    Destroyed(u16),
+    /// One of the given team's units has moved onto the given tile.
+    Enter(u16, (i16, i16)),
+}
+
+/// Something a level's script makes happen.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Effect {
+    /// Shows a line of text across the top of the grid.
+    Message(String),
+    /// Changes the tile at the given position.
+    Cell((i16, i16), Cell),
+    /// Puts a new unit of the given kind on the given team at the given position, or as near to
+    /// it as there is room (see `Game::spawn`).
+    Spawn(String, u16, (i16, i16)),
+    /// Ends the game, with the given team the winner.
+    Win(u16),
+}
+
+fn parse_pos(x: &str, y: &str) -> (i16, i16) {
+    (x.parse().unwrap(), y.parse().unwrap())
+}
+
+/// The name scripts use for a kind of cell, such as `upload-zone`.
+pub fn cell_name(cell: Cell) -> String {
+    cell.name().to_lowercase().replace(" ", "-")
+}
+
+/// The kind of cell with the given `cell_name`.
+pub fn parse_cell(name: &str) -> Cell {
+    CELLS.iter().cloned()
+        .find(|&cell| cell_name(cell) == name)
+        .unwrap_or_else(|| panic!("unknown cell: {}", name))
+}
+
+impl Trigger {
+    /// Parses a trigger from the start of the words of a level directive, such as `enter 0 3 4`,
+    /// returning it and how many words it took up.
+    pub fn from_words(words: &[&str]) -> (Trigger, usize) {
+        assert!(!words.is_empty(), "expected a trigger");
+        let len = match words[0] {
+            "round" | "turn" | "destroyed" => 2,
+            "enter" => 4,
+            t => panic!("unknown trigger: {}", t),
+        };
+        assert!(words.len() >= len, "not enough words for trigger `{}`", words[0]);
+        let trigger = match words[0] {
+            "round" => Trigger::Round(words[1].parse().unwrap()),
+            "turn" => Trigger::Turn(words[1].parse().unwrap()),
+            "destroyed" => Trigger::Destroyed(words[1].parse().unwrap()),
+            _ => Trigger::Enter(words[1].parse().unwrap(), parse_pos(words[2], words[3])),
+        };
+        (trigger, len)
+    }
+
+    /// The trigger that `event` sets off, if any.
+    pub fn from_event(event: &Event) -> Option<Trigger> {
+        match *event {
+            Event::RoundStarted { round } => Some(Trigger::Round(round)),
+            Event::TurnStarted { team, .. } => Some(Trigger::Turn(team)),
+            Event::UnitDestroyed { team, .. } => Some(Trigger::Destroyed(team)),
+            Event::UnitMoved { team, to, .. } => Some(Trigger::Enter(team, to)),
+            _ => None,
+        }
+    }
+
+    /// The team the trigger is about, if there is one.
+    pub fn team(&self) -> Option<u16> {
+        match *self {
+            Trigger::Round(_) => None,
+            Trigger::Turn(team) | Trigger::Destroyed(team) | Trigger::Enter(team, _) => Some(team),
+        }
+    }
+}
+
+impl Effect {
+    /// Parses an effect from the words of a level directive, such as `message Hello there`.
+    pub fn from_words(words: &[&str]) -> Effect {
+        assert!(!words.is_empty(), "expected an effect");
+        match words[0] {
+            "message" => {
+                assert!(words.len() > 1, "expected `message <text...>`");
+                Effect::Message(words[1..].join(" "))
+            },
+            "cell" => {
+                assert!(words.len() == 4, "expected `cell <x> <y> <cell>`");
+                Effect::Cell(parse_pos(words[1], words[2]), parse_cell(words[3]))
+            },
+            "spawn" => {
+                assert!(words.len() == 5, "expected `spawn <kind> <team> <x> <y>`");
+                assert!(Unit::from_kind(words[1], (0, 0)).is_some(),
+                        "unknown unit kind: {}", words[1]);
+                Effect::Spawn(words[1].into(), words[2].parse().unwrap(),
+                              parse_pos(words[3], words[4]))
+            },
+            "win" => {
+                assert!(words.len() == 2, "expected `win <team>`");
+                Effect::Win(words[1].parse().unwrap())
+            },
+            e => panic!("unknown effect: {}", e),
+        }
+    }
+
+    /// The team the effect is about, if there is one.
+    pub fn team(&self) -> Option<u16> {
+        match *self {
+            Effect::Message(_) | Effect::Cell(..) => None,
+            Effect::Spawn(_, team, _) | Effect::Win(team) => Some(team),
+        }
+    }
+
+    pub fn apply(&self, game: &mut Game) {
+        match *self {
+            Effect::Message(ref text) => game.show_message(text),
+            Effect::Cell(pos, cell) => {
+                if !game.grid.is_in_bounds(pos.0, pos.1) { return }
+                // Units are never left standing on tiles they couldn't have moved onto
+                if !cell.is_walkable() {
+                    if game.units.values().any(|unit| unit.occupies(pos.0, pos.1)) { return }
+                    game.items.retain(|item| item.pos != pos);
+                }
+                let before = game.grid[pos];
+                if before == Cell::Empty && cell != Cell::Empty {
+                    game.anims.add(game.frame, anim::Effect::Rise { pos: pos });
+                }
+                game.grid[pos] = cell;
+                if before != cell {
+                    game.emit(Event::TileChanged {
+                        pos: pos, from: before, to: cell, cause: None
+                    });
+                }
+                game.anims.add(game.frame, anim::Effect::Flash { pos: pos });
+            },
+            Effect::Spawn(ref kind, team, pos) => {
+                let mut unit = Unit::from_kind(kind, pos).unwrap();
+                unit.team = team;
+                if game.spawn(unit).is_none() {
+                    eprintln!("no room to spawn a {} at {:?}", kind, pos);
+                }
+            },
+            Effect::Win(team) => game.victor = Some(team),
+        }
+    }
+}
+
+/// Writes the trigger out in the form `Trigger::from_words` reads.
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Trigger::Round(round) => write!(f, "round {}", round),
+            Trigger::Turn(team) => write!(f, "turn {}", team),
+            Trigger::Destroyed(team) => write!(f, "destroyed {}", team),
+            Trigger::Enter(team, (x, y)) => write!(f, "enter {} {} {}", team, x, y),
+        }
+    }
+}
+
+/// Writes the effect out in the form `Effect::from_words` reads.
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Effect::Message(ref text) => write!(f, "message {}", text),
+            Effect::Cell((x, y), cell) => write!(f, "cell {} {} {}", x, y, cell_name(cell)),
+            Effect::Spawn(ref kind, team, (x, y)) => {
+                write!(f, "spawn {} {} {} {}", kind, team, x, y)
+            },
+            Effect::Win(team) => write!(f, "win {}", team),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use grid::Cell;
+    use super::{Trigger, Effect};
+
+    #[test]
+    fn triggers_and_effects_are_written_as_they_are_read() {
+        let line = "enter 1 3 -2 cell 3 4 upload-zone";
+        let words: Vec<_> = line.split_whitespace().collect();
+        let (trigger, len) = Trigger::from_words(&words);
+        let effect = Effect::from_words(&words[len..]);
+        assert_eq!(trigger, Trigger::Enter(1, (3, -2)));
+        assert_eq!(effect, Effect::Cell((3, 4), Cell::Upload));
+        assert_eq!(format!("{} {}", trigger, effect), line);
+
+        for line in &["round 3", "turn 0", "destroyed 2"] {
+            let words: Vec<_> = line.split_whitespace().collect();
+            let (trigger, len) = Trigger::from_words(&words);
+            assert_eq!(len, 2);
+            assert_eq!(trigger.to_string(), *line);
+        }
+        for line in &["message Watch  out!", "spawn sentinel 1 0 5", "win 1"] {
+            let words: Vec<_> = line.split_whitespace().collect();
+            let effect = Effect::from_words(&words);
+            assert_eq!(Effect::from_words(&effect.to_string().split_whitespace()
+                                          .collect::<Vec<_>>()), effect);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown trigger: sunset")]
+    fn unknown_triggers_are_errors() {
+        Trigger::from_words(&["sunset", "message", "hi"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "not enough words for trigger `enter`")]
+    fn short_triggers_are_errors() {
+        Trigger::from_words(&["enter", "0", "1"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown cell: lava")]
+    fn unknown_cells_are_errors() {
+        Effect::from_words(&["cell", "0", "0", "lava"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown unit kind: dragon")]
+    fn unknown_unit_kinds_are_errors() {
+        Effect::from_words(&["spawn", "dragon", "1", "0", "0"]);
+    }
+}
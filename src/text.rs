@@ -0,0 +1,149 @@
+//! Drawing text with the bitmap font in `assets/font.txt`.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use graphics::{Context, rectangle};
+use opengl_graphics::GlGraphics;
+
+pub struct Font {
+    width: usize,
+    height: usize,
+    /// The pixels of each glyph, row by row.
+    glyphs: HashMap<char, Vec<bool>>,
+}
+
+impl Font {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Font {
+        let mut f = File::open(path).unwrap();
+        let mut s = String::new();
+        f.read_to_string(&mut s).unwrap();
+        Font::from_string(&s)
+    }
+
+    pub fn from_string(s: &str) -> Font {
+        let mut size = None;
+        let mut glyphs = HashMap::new();
+        let mut current: Option<(char, Vec<bool>)> = None;
+        for line in s.lines() {
+            if line.starts_with(";") || line.is_empty() { continue }
+            if line.starts_with("size ") {
+                let words: Vec<usize> = line[5..].split_whitespace()
+                                                 .map(|w| w.parse().unwrap()).collect();
+                assert!(words.len() == 2, "expected `size <width> <height>`");
+                size = Some((words[0], words[1]));
+            } else if line.starts_with(":") {
+                if let Some((c, pixels)) = current.take() {
+                    glyphs.insert(c, pixels);
+                }
+                current = Some((line[1..].chars().next().unwrap_or(' '), vec![]));
+            } else {
+                let (width, _) = size.expect("font size must come before the glyphs");
+                let &mut (c, ref mut pixels) = current.as_mut().expect("pixels outside of a glyph");
+                assert!(line.len() == width, "row of the wrong width in glyph {:?}", c);
+                pixels.extend(line.chars().map(|p| p == '#'));
+            }
+        }
+        if let Some((c, pixels)) = current.take() {
+            glyphs.insert(c, pixels);
+        }
+
+        let (width, height) = size.expect("font has no size");
+        for (c, pixels) in &glyphs {
+            assert!(pixels.len() == width * height, "glyph {:?} has the wrong height", c);
+        }
+        Font {
+            width: width,
+            height: height,
+            glyphs: glyphs,
+        }
+    }
+
+    /// The width of `text` in pixels when drawn at the given scale.
+    pub fn width(&self, text: &str, scale: f64) -> f64 {
+        let len = text.chars().count();
+        if len == 0 { return 0.0 }
+        // Glyphs are separated by a blank column
+        (len * (self.width + 1) - 1) as f64 * scale
+    }
+
+    /// The height of a line of text in pixels when drawn at the given scale.
+    pub fn height(&self, scale: f64) -> f64 {
+        self.height as f64 * scale
+    }
+
+    fn glyph(&self, c: char) -> &[bool] {
+        self.glyphs.get(&c).or_else(|| self.glyphs.get(&'?')).map(|g| &g[..]).unwrap_or(&[])
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Centre,
+    Right,
+}
+
+/// How to draw a piece of text.
+#[derive(Copy, Clone, Debug)]
+pub struct Text {
+    pub colour: [f32; 4],
+    /// How many screen pixels wide each pixel of the font is.
+    pub scale: f64,
+    pub align: Align,
+}
+
+impl Text {
+    pub fn new(colour: [f32; 4]) -> Text {
+        Text {
+            colour: colour,
+            scale: 1.0,
+            align: Align::Left,
+        }
+    }
+
+    pub fn colour(mut self, value: [f32; 4]) -> Self {
+        self.colour = value;
+        self
+    }
+
+    pub fn scale(mut self, value: f64) -> Self {
+        self.scale = value;
+        self
+    }
+
+    pub fn align(mut self, value: Align) -> Self {
+        self.align = value;
+        self
+    }
+
+    /// Draws `text` with its top edge at `y`. Depending on the alignment, `x` is where the left
+    /// edge, the middle or the right edge of the text goes.
+    pub fn draw(&self, text: &str, font: &Font, x: f64, y: f64, c: &Context,
+                gl: &mut GlGraphics) {
+        let mut x = match self.align {
+            Align::Left => x,
+            Align::Centre => x - font.width(text, self.scale) / 2.0,
+            Align::Right => x - font.width(text, self.scale),
+        };
+        for ch in text.chars() {
+            let glyph = font.glyph(ch);
+            for (row, pixels) in glyph.chunks(font.width).enumerate() {
+                // Draw each horizontal run of lit pixels as one rectangle
+                let mut col = 0;
+                while col < pixels.len() {
+                    if !pixels[col] { col += 1; continue }
+                    let start = col;
+                    while col < pixels.len() && pixels[col] { col += 1 }
+                    rectangle(self.colour,
+                              [x + start as f64 * self.scale, y + row as f64 * self.scale,
+                               (col - start) as f64 * self.scale, self.scale],
+                              c.transform,
+                              gl);
+                }
+            }
+            x += (font.width + 1) as f64 * self.scale;
+        }
+    }
+}
@@ -1,11 +1,21 @@
-use super::{Game, CELL_SIZE, CELL_PADDING, CELL_OFFSET_X, CELL_OFFSET_Y, cell_pos};
+use super::{Game, CELL_SIZE, CELL_PADDING, cell_pos};
 use grid::Cell;
+use anim::Effect;
+use event::Event;
 use std::collections::VecDeque;
 use graphics::Context;
 use opengl_graphics::GlGraphics;
 
+/// Which unit is which for the whole of a game. Ids aren't reused once the unit they belong to
+/// has been destroyed, so an id can only ever refer to one unit.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UnitId(pub u32);
+
 #[derive(Clone)]
 pub struct Unit {
+    pub name: &'static str,
+    /// The name used for the unit's kind in level files (see `from_kind`).
+    pub kind: &'static str,
     pub parts: VecDeque<(i16, i16)>,
     pub len_limit: usize,
     pub selected: bool,
@@ -15,6 +25,8 @@ pub struct Unit {
     pub team: u16,
     pub attack: Option<u16>,
     pub attacks: Vec<Attack>,
+    /// How far away from its cells the unit can see when there is fog of war.
+    pub sight: u16,
     colour: [f32; 3],
     texture: usize,
 }
@@ -22,11 +34,16 @@ pub struct Unit {
 #[derive(Copy)]
 pub enum Attack {
     UnitTargetting {
+        name: &'static str,
         range: u16,
-        /// When the second parameter is `None`, the unit is attacking itself.
-        perform: fn(&mut Unit, Option<&mut Unit>) -> bool,
+        /// How many cells the attack removes from its target.
+        damage: u16,
+        /// When the second parameter is `None`, the unit is attacking itself. The third is the
+        /// attack's `damage`, so that it is only written down in one place.
+        perform: fn(&mut Unit, Option<&mut Unit>, u16) -> bool,
     },
     GroundTargetting {
+        name: &'static str,
         range: u16,
         /// Can the attack target solid tiles?
         full: bool,
@@ -39,14 +56,17 @@ pub enum Attack {
 impl Clone for Attack {
     fn clone(&self) -> Attack {
         match *self {
-            Attack::UnitTargetting { range, perform } => {
+            Attack::UnitTargetting { name, range, damage, perform } => {
                 Attack::UnitTargetting {
+                    name: name,
                     range: range,
+                    damage: damage,
                     perform: perform,
                 }
             }
-            Attack::GroundTargetting { range, full, empty, perform } => {
+            Attack::GroundTargetting { name, range, full, empty, perform } => {
                 Attack::GroundTargetting {
+                    name: name,
                     range: range,
                     full: full,
                     empty: empty,
@@ -57,50 +77,58 @@ impl Clone for Attack {
     }
 }
 
-macro_rules! simple_attack {
-    ($damage: expr) => {{
-        fn tmp(slf: &mut Unit, unit: Option<&mut Unit>) -> bool {
-            match unit {
-                Some(unit) => unit.damage($damage),
-                None => slf.damage($damage),
-            }
-            true
-        }
-        tmp
-    }}
+/// An attack that just removes `damage` cells from its target.
+fn simple_attack(slf: &mut Unit, unit: Option<&mut Unit>, damage: u16) -> bool {
+    match unit {
+        Some(unit) => unit.damage(damage),
+        None => slf.damage(damage),
+    }
+    true
+}
+
+/// Whether a ground-targetting attack that can target solid tiles if `full` and empty tiles if
+/// `empty` can be used on a tile of the given cell. Walls can't be targetted at all.
+fn can_target(cell: Cell, full: bool, empty: bool) -> bool {
+    match cell {
+        Cell::Wall => false,
+        Cell::Empty => empty,
+        _ => full,
+    }
 }
 
 impl Attack {
     pub fn slice() -> Attack {
         Attack::UnitTargetting {
+            name: "Slice",
             range: 1,
-            perform: simple_attack!(2),
+            damage: 2,
+            perform: simple_attack,
         }
     }
 
     pub fn dice() -> Attack {
-        fn atk(slf: &mut Unit, unit: Option<&mut Unit>) -> bool {
+        fn atk(slf: &mut Unit, unit: Option<&mut Unit>, damage: u16) -> bool {
             if slf.parts.len() < 3 {
                 return false
             }
 
-            match unit {
-                Some(unit) => unit.damage(3),
-                None => slf.damage(3),
-            }
-            true
+            simple_attack(slf, unit, damage)
         }
 
         Attack::UnitTargetting {
+            name: "Dice",
             range: 1,
+            damage: 3,
             perform: atk,
         }
     }
 
     pub fn thump() -> Attack {
         Attack::UnitTargetting {
+            name: "Thump",
             range: 1,
-            perform: simple_attack!(3),
+            damage: 3,
+            perform: simple_attack,
         }
     }
 
@@ -110,6 +138,7 @@ impl Attack {
             true
         }
         Attack::GroundTargetting {
+            name: "One",
             range: 3,
             full: false,
             empty: true,
@@ -117,14 +146,55 @@ impl Attack {
         }
     }
 
+    /// Creates an attack from its name in lowercase, as used in profiles.
+    pub fn from_name(name: &str) -> Option<Attack> {
+        Some(match name {
+            "slice" => Attack::slice(),
+            "dice" => Attack::dice(),
+            "thump" => Attack::thump(),
+            "one" => Attack::one(),
+            _ => return None,
+        })
+    }
+
+    /// How many credits it costs to teach a unit the attack with the given name.
+    pub fn price(name: &str) -> Option<u32> {
+        Some(match name {
+            "slice" => 30,
+            "dice" => 60,
+            "thump" => 60,
+            "one" => 40,
+            _ => return None,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Attack::UnitTargetting { name, .. }
+            | Attack::GroundTargetting { name, .. } => name,
+        }
+    }
+
     pub fn range(&self) -> u16 {
         match *self {
             Attack::UnitTargetting { range, .. }
             | Attack::GroundTargetting { range, .. } => range,
         }
     }
+
+    pub fn damage(&self) -> u16 {
+        match *self {
+            Attack::UnitTargetting { damage, .. } => damage,
+            Attack::GroundTargetting { .. } => 0,
+        }
+    }
 }
 
+/// The names of every kind of unit, as used in level files.
+pub const KINDS: [&'static str; 4] = ["hack", "lightning", "warden", "sentinel"];
+/// The names of every attack, as used in profiles.
+pub const ATTACKS: [&'static str; 4] = ["slice", "dice", "thump", "one"];
+
 impl Unit {
     pub fn from_char(c: char, coords: (i16, i16)) -> Unit {
         match c {
@@ -138,8 +208,32 @@ impl Unit {
         }
     }
 
+    /// Creates a unit from the name used for it in level files.
+    pub fn from_kind(kind: &str, coords: (i16, i16)) -> Option<Unit> {
+        Some(match kind {
+            "hack" => Unit::sample(coords),
+            "lightning" => Unit::sample2(coords),
+            "warden" => Unit::sample_enemy(coords),
+            "sentinel" => Unit::sample_enemy2(coords),
+            _ => return None,
+        })
+    }
+
+    /// How many credits a unit of the given kind costs in the shop.
+    pub fn price(kind: &str) -> Option<u32> {
+        Some(match kind {
+            "hack" => 50,
+            "lightning" => 80,
+            "warden" => 120,
+            "sentinel" => 150,
+            _ => return None,
+        })
+    }
+
     pub fn sample(coords: (i16, i16)) -> Unit {
         Unit {
+            name: "Hack",
+            kind: "hack",
             parts: { let mut v = VecDeque::new(); v.push_back(coords); v },
             len_limit: 4,
             selected: false,
@@ -149,6 +243,7 @@ impl Unit {
             has_attacked: false,
             team: 0,
             attacks: vec![Attack::slice(), Attack::dice()],
+            sight: 3,
             colour: [0.0, 0.7490196078431373, 0.9686274509803922],
             texture: 1,
         }
@@ -156,6 +251,8 @@ impl Unit {
 
     pub fn sample2(coords: (i16, i16)) -> Unit {
         Unit {
+            name: "Lightning",
+            kind: "lightning",
             parts: { let mut v = VecDeque::new(); v.push_back(coords); v },
             len_limit: 4,
             selected: false,
@@ -165,6 +262,7 @@ impl Unit {
             has_attacked: false,
             team: 0,
             attacks: vec![Attack::slice(), Attack::dice()],
+            sight: 4,
             colour: [0.5647058823529412, 0.9882352941176471, 0.0],
             texture: 2,
         }
@@ -172,6 +270,8 @@ impl Unit {
 
     pub fn sample_enemy(coords: (i16, i16)) -> Unit {
         Unit {
+            name: "Warden",
+            kind: "warden",
             parts: { let mut v = VecDeque::new(); v.push_back(coords); v },
             len_limit: 5,
             selected: false,
@@ -181,6 +281,7 @@ impl Unit {
             has_attacked: false,
             team: 1,
             attacks: vec![Attack::thump()],
+            sight: 2,
             colour: [0.9725490196078431, 0.0, 0.06666666666666667],
             texture: 3,
         }
@@ -188,6 +289,8 @@ impl Unit {
 
     pub fn sample_enemy2(coords: (i16, i16)) -> Unit {
         Unit {
+            name: "Sentinel",
+            kind: "sentinel",
             parts: { let mut v = VecDeque::new(); v.push_back(coords); v },
             len_limit: 5,
             selected: false,
@@ -197,6 +300,7 @@ impl Unit {
             has_attacked: false,
             team: 1,
             attacks: vec![Attack::thump()],
+            sight: 3,
             colour: [0.5647058823529412, 1.0, 0.9882352941176471],
             texture: 2,
         }
@@ -218,22 +322,56 @@ impl Unit {
             game.save_with(self.clone());
             game.done = true;
         }
+        game.anims.add(game.frame, Effect::Slide { from: (headx, heady), to: new });
         if let Some(idx) = self.parts.iter().position(|x| *x == new) {
             let val = self.parts.remove(idx).unwrap();
             self.parts.push_front(val);
-            self.moves -= 1;
-            self.highlight(game);
-            return
-        }
-
-        self.parts.push_front((headx + dx, heady + dy));
-        if self.parts.len() > self.len_limit {
-            self.shorten();
+        } else {
+            self.parts.push_front(new);
+            if self.parts.len() > self.len_limit {
+                self.shorten();
+            }
         }
         self.moves -= 1;
+        if let Some(id) = game.acting_unit() {
+            let from = (headx, heady);
+            game.emit(Event::UnitMoved { unit: id, team: self.team, from: from, to: new });
+        }
+        self.enter(game, new);
         self.highlight(game);
     }
 
+    /// Applies the effects of the cell at `pos` and picks up any item there, now that the
+    /// unit has moved its head onto it.
+    fn enter(&mut self, game: &mut Game, pos: (i16, i16)) {
+        if let Some(i) = game.items.iter().position(|item| item.pos == pos) {
+            let item = game.items.remove(i);
+            game.teams[self.team as usize].credits += item.credits;
+            game.anims.add(game.frame, Effect::Flash { pos: pos });
+        }
+        match game.grid[pos] {
+            Cell::Data => {
+                self.len_limit += 1;
+                game.grid[pos] = Cell::Floor;
+                game.emit(Event::TileChanged {
+                    pos: pos, from: Cell::Data, to: Cell::Floor, cause: None
+                });
+                game.anims.add(game.frame, Effect::Flash { pos: pos });
+            },
+            Cell::Slow => self.moves = 0,
+            // Terrain wears units down but never destroys them
+            Cell::Damage if self.parts.len() > 1 => {
+                let before = self.parts.clone();
+                self.shorten();
+                self.sink_lost(game, &before);
+                if let Some(id) = game.acting_unit() {
+                    game.emit(Event::UnitDamaged { unit: id, cause: None, amount: 1 });
+                }
+            },
+            _ => {},
+        }
+    }
+
     fn shorten(&mut self) {
         self.parts.pop_back();
     }
@@ -270,6 +408,8 @@ impl Unit {
         if game.grid.highlight[pos] >= moves + 1 { return }
         game.grid.highlight[pos] = moves + 1;
         if moves == 0 { return }
+        // Slow tiles can be moved onto but not through
+        if game.grid[(x, y)] == Cell::Slow && (x, y) != self.parts[0] { return }
         self._highlight(game, moves - 1, x + 1, y);
         self._highlight(game, moves - 1, x - 1, y);
         self._highlight(game, moves - 1, x, y + 1);
@@ -277,6 +417,7 @@ impl Unit {
     }
 
     fn _attack_highlight(&self, game: &mut Game, moves: u16, x: i16, y: i16) {
+        if game.grid.is_in_bounds(x, y) && game.grid[(x, y)] == Cell::Wall { return }
         let mut good = true;
         let mut allow_empty = false;
         if let Some(attack) = self.attack {
@@ -286,8 +427,7 @@ impl Unit {
                     if !game.grid.is_in_bounds(x, y) {
                         good = false
                     } else {
-                        if (full || game.grid[(x, y)] != Cell::Floor)
-                        && (empty || game.grid[(x, y)] != Cell::Empty) {
+                        if can_target(game.grid[(x, y)], full, empty) {
                             allow_empty = true;
                         } else {
                             good = false;
@@ -351,33 +491,51 @@ impl Unit {
         debug_assert!(self.attack.is_some());
         if let Some(atk) = self.attack {
             let coords = game.grid.attack_loc.unwrap();
+            let cause = game.acting_unit().map(|id| (id, self.attacks[atk as usize].name()));
             match self.attacks[atk as usize] {
-                Attack::UnitTargetting { perform, .. } => {
-                    if let Some((idx, _)) = game.units.iter_mut().find(|&(_, ref unit)| {
+                Attack::UnitTargetting { perform, damage, .. } => {
+                    let target = game.units.iter().find(|&(_, unit)| {
                         for &ucoords in &unit.parts {
                             if coords == ucoords { return true }
                         }
                         false
-                    }) {
+                    }).map(|(&id, _)| id);
+                    if let Some(id) = target {
                         let mut target_is_kill = false;
+                        let before = game.units[&id].parts.clone();
                         {
-                            if perform(self, Some(&mut game.units[idx])) {
-                                let target = &mut game.units[idx];
+                            if perform(self, game.unit_mut(id), damage) {
+                                let target = game.units[&id].clone();
                                 if target.parts.len() == 0 {
                                     target_is_kill = true;
                                 }
+                                target.sink_lost(game, &before);
+                                let amount = before.len() - target.parts.len();
+                                game.emit(Event::UnitDamaged {
+                                    unit: id, cause: cause, amount: amount
+                                });
+                                game.anims.add(game.frame, Effect::Flash { pos: coords });
                                 self.moves = 0;
                                 self.has_attacked = true;
                                 game.undo.push(save);
                             }
                         }
                         if target_is_kill {
-                            // no
-                            game.units.remove(&idx);
+                            let target = game.units.remove(&id).unwrap();
+                            game.emit(Event::UnitDestroyed { unit: id, team: target.team });
                         }
                     } else {
                         if self.parts.iter().find(|&&x| x == coords).is_some() {
-                            if perform(self, None) {
+                            let before = self.parts.clone();
+                            if perform(self, None, damage) {
+                                self.sink_lost(game, &before);
+                                if let Some((id, _)) = cause {
+                                    let amount = before.len() - self.parts.len();
+                                    game.emit(Event::UnitDamaged {
+                                        unit: id, cause: cause, amount: amount
+                                    });
+                                }
+                                game.anims.add(game.frame, Effect::Flash { pos: coords });
                                 self.moves = 0;
                                 self.has_attacked = true;
                                 game.undo.push(save);
@@ -387,9 +545,19 @@ impl Unit {
                     }
                 },
                 Attack::GroundTargetting { perform, full, empty, .. } => {
-                    if (full || game.grid[coords] != Cell::Floor)
-                    && (empty || game.grid[coords] != Cell::Empty) {
+                    if can_target(game.grid[coords], full, empty) {
+                        let before = game.grid[coords];
                         if perform(self, game, coords) {
+                            if before == Cell::Empty && game.grid[coords] != Cell::Empty {
+                                game.anims.add(game.frame, Effect::Rise { pos: coords });
+                            }
+                            let after = game.grid[coords];
+                            if after != before {
+                                game.emit(Event::TileChanged {
+                                    pos: coords, from: before, to: after, cause: cause
+                                });
+                            }
+                            game.anims.add(game.frame, Effect::Flash { pos: coords });
                             self.moves = 0;
                             self.has_attacked = true;
                             game.undo.push(save);
@@ -401,6 +569,15 @@ impl Unit {
         }
     }
 
+    /// Animates the cells the unit had in `before` but has since lost sinking away.
+    fn sink_lost(&self, game: &mut Game, before: &VecDeque<(i16, i16)>) {
+        for &(x, y) in before {
+            if !self.occupies(x, y) {
+                game.anims.add(game.frame, Effect::Sink { pos: (x, y), colour: self.colour });
+            }
+        }
+    }
+
     pub fn leave_attack(&mut self, game: &mut Game) {
         self.attack = None;
         game.grid.attack_loc = None;
@@ -411,6 +588,15 @@ impl Unit {
         self._draw(game, c, gl);
     }
 
+    /// Draws the unit's icon on its colour, filling `rect`.
+    pub fn draw_icon(&self, game: &Game, rect: [f64; 4], c: &Context, gl: &mut GlGraphics) {
+        use graphics::*;
+        let (r, g, b) = (self.colour[0], self.colour[1], self.colour[2]);
+        rectangle([r, g, b, 1.0], rect, c.transform, gl);
+        Image::new().rect(rect)
+              .draw(&game.textures[self.texture], default_draw_state(), c.transform, gl);
+    }
+
     fn _draw(&mut self, game: &Game, c: &Context, gl: &mut GlGraphics) {
         use graphics::*;
 
@@ -419,6 +605,7 @@ impl Unit {
         parts.sort();
 
         for &&(x, y) in &parts {
+            if !game.is_shown(x as usize + game.grid.width*y as usize) { continue }
             let is_last = |coords|
                 self.len_limit > 1
                 && self.moves > 0
@@ -426,6 +613,11 @@ impl Unit {
                 && self.attack.is_none()
                 && self.parts.len() == self.len_limit && coords == self.parts[self.parts.len() - 1];
             let alpha = if is_last((x, y)) { (game.frame / 3 % 2) as f32 } else { 1.0 };
+            let (ox, oy) = if (x, y) == self.parts[0] {
+                game.anims.slide_offset(game.frame, (x, y))
+            } else {
+                (0.0, 0.0)
+            };
             for i in -1..3 {
                 let i = i as f64;
                 let mut colour = [[r * 0.57, g * 0.57, b * 0.57, alpha],
@@ -434,8 +626,8 @@ impl Unit {
                                   [r       , g       , b       , alpha]][(i + 1.0) as usize];
                 let mut extra = 0.0;
                 if i == 2.0 { extra = 1.0; }
-                let rect = [cell_pos(x) - i + extra,
-                            cell_pos(y) - i + extra,
+                let rect = [cell_pos(x) - i + extra + ox,
+                            cell_pos(y) - i + extra + oy,
                             CELL_SIZE - extra, CELL_SIZE - extra];
                 rectangle(colour,
                           rect,
@@ -466,11 +658,19 @@ impl Unit {
         }
         // Draw icon + glow
         let (x, y) = self.parts[0];
-        let rect = [CELL_OFFSET_X + x as f64 * (CELL_SIZE + CELL_PADDING) - 1.0,
-                    CELL_OFFSET_Y + y as f64 * (CELL_SIZE + CELL_PADDING) - 1.0,
+        if !game.is_shown(x as usize + game.grid.width*y as usize) { return }
+        let (ox, oy) = game.anims.slide_offset(game.frame, (x, y));
+        let rect = [cell_pos(x) - 1.0 + ox,
+                    cell_pos(y) - 1.0 + oy,
                     CELL_SIZE - 1.0, CELL_SIZE - 1.0];
         Image::new().rect(rect)
               .draw(&game.textures[self.texture], default_draw_state(), c.transform, gl);
+        // Mark which team the unit belongs to
+        let team = game.teams[self.team as usize].colour;
+        rectangle([team[0], team[1], team[2], 1.0],
+                  [rect[0] + rect[2] - 6.0, rect[1] + rect[3] - 6.0, 6.0, 6.0],
+                  c.transform,
+                  gl);
         let border = [rect[0], rect[1], rect[2] + 3.5, rect[3] + 2.5];
         if self.selected {
             Rectangle::new_border([1.0, 1.0, 1.0, 1.0 - (game.frame % 40) as f32 / 39.0], 1.0)